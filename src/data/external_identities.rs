@@ -0,0 +1,197 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::tokens;
+use crate::users::AccountType;
+
+pub const LOGIN_ATTEMPT_TTL_SECONDS: i64 = 10 * 60;
+pub const REAUTHENTICATION_TTL_SECONDS: i64 = 5 * 60;
+
+// Why the user was sent to the provider. Linking and reauthenticating start from a signed-in
+// session and can only be finished by that same account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    SignIn,
+    Link,
+    Reauthenticate,
+}
+
+impl Purpose {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Purpose::SignIn => "sign_in",
+            Purpose::Link => "link",
+            Purpose::Reauthenticate => "reauthenticate",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sign_in" => Some(Purpose::SignIn),
+            "link" => Some(Purpose::Link),
+            "reauthenticate" => Some(Purpose::Reauthenticate),
+            _ => None,
+        }
+    }
+}
+
+// What starting a provider sign-in remembers until the provider sends the user back.
+// The state itself is only stored hashed; the client carries it through the redirect.
+pub struct LoginAttempt {
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    // Used if the sign-in ends up creating an account.
+    pub account_type: AccountType,
+    pub purpose: Purpose,
+    // The signed-in account that started a link or reauthentication.
+    pub account_id: Option<String>,
+}
+
+pub fn begin_login(conn: &Connection, state: &str, attempt: &LoginAttempt) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute("DELETE FROM oidc_login_attempts WHERE expires_at < ?1", [now - 24 * 60 * 60])?;
+    conn.execute(
+        "INSERT INTO oidc_login_attempts (state_hash, provider, code_verifier, nonce, account_type, purpose, account_id, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            tokens::hash_opaque_token(state),
+            attempt.provider,
+            attempt.code_verifier,
+            attempt.nonce,
+            attempt.account_type,
+            attempt.purpose.as_str(),
+            attempt.account_id,
+            now,
+            now + LOGIN_ATTEMPT_TTL_SECONDS
+        ],
+    )?;
+    Ok(())
+}
+
+// Each state is good for a single callback from the provider it was issued for, until it
+// expires, and only for the purpose (and account) it was started with.
+pub fn take_login(conn: &Connection, provider: &str, state: &str, purpose: Purpose, account_id: Option<&str>) -> rusqlite::Result<Option<LoginAttempt>> {
+    conn.query_row(
+        "UPDATE oidc_login_attempts SET consumed_at = ?1
+         WHERE state_hash = ?2 AND provider = ?3 AND consumed_at IS NULL AND expires_at > ?1
+           AND purpose = ?4 AND account_id IS ?5
+         RETURNING provider, code_verifier, nonce, account_type, account_id",
+        params![chrono::Utc::now().timestamp(), tokens::hash_opaque_token(state), provider, purpose.as_str(), account_id],
+        |row| Ok(LoginAttempt {
+            provider: row.get(0)?,
+            code_verifier: row.get(1)?,
+            nonce: row.get(2)?,
+            account_type: row.get(3)?,
+            purpose,
+            account_id: row.get(4)?,
+        }),
+    ).optional()
+}
+
+// The account an external identity is linked to, by the provider's stable subject id.
+pub fn find_account(conn: &Connection, provider: &str, subject: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT account_id FROM external_identities WHERE provider = ?1 AND subject = ?2",
+        params![provider, subject],
+        |row| row.get(0),
+    ).optional()
+}
+
+pub fn link(conn: &Connection, account_id: &str, provider: &str, subject: &str, email: Option<&str>) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO external_identities (account_id, provider, subject, email, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![account_id, provider, subject, email, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn record_login(conn: &Connection, provider: &str, subject: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE external_identities SET last_login_at = ?1 WHERE provider = ?2 AND subject = ?3",
+        params![chrono::Utc::now().timestamp(), provider, subject],
+    )?;
+    Ok(())
+}
+
+// Accounts without a password prove they are still theirs by signing in with a linked
+// provider again. That counts once, for the session it was done from, for a few minutes.
+pub fn record_reauthentication(conn: &Connection, account_id: &str, session_id: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO reauthentications (session_id, account_id, authenticated_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT (session_id) DO UPDATE SET account_id = ?2, authenticated_at = ?3",
+        params![session_id, account_id, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn take_reauthentication(conn: &Connection, account_id: &str, session_id: &str) -> rusqlite::Result<bool> {
+    let taken = conn.execute(
+        "DELETE FROM reauthentications WHERE session_id = ?1 AND account_id = ?2 AND authenticated_at > ?3",
+        params![session_id, account_id, chrono::Utc::now().timestamp() - REAUTHENTICATION_TTL_SECONDS],
+    )?;
+    Ok(taken > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::accounts::tests::insert_account;
+
+    fn attempt(purpose: Purpose, account_id: Option<&str>) -> LoginAttempt {
+        LoginAttempt {
+            provider: "mock".to_string(),
+            code_verifier: "verifier".to_string(),
+            nonce: "nonce".to_string(),
+            account_type: AccountType::Student,
+            purpose,
+            account_id: account_id.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn login_state_is_single_use() {
+        let conn = crate::repository::test_connection();
+        begin_login(&conn, "state", &attempt(Purpose::SignIn, None)).unwrap();
+
+        assert!(take_login(&conn, "other", "state", Purpose::SignIn, None).unwrap().is_none());
+        assert!(take_login(&conn, "mock", "wrong", Purpose::SignIn, None).unwrap().is_none());
+        let taken = take_login(&conn, "mock", "state", Purpose::SignIn, None).unwrap().unwrap();
+        assert_eq!(taken.nonce, "nonce");
+        assert!(take_login(&conn, "mock", "state", Purpose::SignIn, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn signed_in_flows_are_bound_to_their_account() {
+        let conn = crate::repository::test_connection();
+        let owner = insert_account(&conn, "owner@example.com", AccountType::Student).unique_id;
+        begin_login(&conn, "link", &attempt(Purpose::Link, Some(&owner))).unwrap();
+
+        assert!(take_login(&conn, "mock", "link", Purpose::SignIn, None).unwrap().is_none());
+        assert!(take_login(&conn, "mock", "link", Purpose::Reauthenticate, Some(&owner)).unwrap().is_none());
+        assert!(take_login(&conn, "mock", "link", Purpose::Link, Some("someone-else")).unwrap().is_none());
+        let taken = take_login(&conn, "mock", "link", Purpose::Link, Some(&owner)).unwrap().unwrap();
+        assert_eq!(taken.account_id.as_deref(), Some(owner.as_str()));
+
+        link(&conn, &owner, "mock", "subject", None).unwrap();
+        assert_eq!(find_account(&conn, "mock", "subject").unwrap(), Some(owner.clone()));
+        assert!(link(&conn, &owner, "mock", "subject", None).is_err());
+    }
+
+    #[test]
+    fn reauthentication_counts_once_for_its_session() {
+        let conn = crate::repository::test_connection();
+        let owner = insert_account(&conn, "owner@example.com", AccountType::Student).unique_id;
+        record_reauthentication(&conn, &owner, "session").unwrap();
+
+        assert!(!take_reauthentication(&conn, &owner, "other-session").unwrap());
+        assert!(!take_reauthentication(&conn, "someone-else", "session").unwrap());
+        assert!(take_reauthentication(&conn, &owner, "session").unwrap());
+        assert!(!take_reauthentication(&conn, &owner, "session").unwrap());
+
+        conn.execute(
+            "INSERT INTO reauthentications (session_id, account_id, authenticated_at) VALUES ('old', ?1, ?2)",
+            params![owner, chrono::Utc::now().timestamp() - REAUTHENTICATION_TTL_SECONDS - 1],
+        ).unwrap();
+        assert!(!take_reauthentication(&conn, &owner, "old").unwrap());
+    }
+}
@@ -0,0 +1,37 @@
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::sessions::ClientInfo;
+
+// Successful sign-ins, kept so users can spot ones that weren't them.
+#[derive(Debug, Serialize)]
+pub struct LoginEvent {
+    pub id: i64,
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub created_at: i64,
+}
+
+pub fn record(conn: &Connection, account_id: &str, client: &ClientInfo) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO login_events (account_id, ip, user_agent, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![account_id, client.ip, client.user_agent, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn recent(conn: &Connection, account_id: &str, limit: i64) -> rusqlite::Result<Vec<LoginEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, ip, user_agent, created_at FROM login_events
+         WHERE account_id = ?1 ORDER BY id DESC LIMIT ?2"
+    )?;
+    let events = stmt.query_map(params![account_id, limit], |row| {
+        Ok(LoginEvent {
+            id: row.get(0)?,
+            ip: row.get(1)?,
+            user_agent: row.get(2)?,
+            created_at: row.get(3)?,
+        })
+    })?;
+    events.collect()
+}
@@ -0,0 +1,607 @@
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+
+// Schema changes are appended here and never edited once released; each one runs
+// exactly once per database, inside its own transaction.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "baseline_schema", up: baseline_schema },
+    Migration { version: 2, name: "refresh_tokens", up: refresh_tokens },
+    Migration { version: 3, name: "posts_rejected_status", up: posts_rejected_status },
+    Migration { version: 4, name: "email_verification", up: email_verification },
+    Migration { version: 5, name: "password_resets", up: password_resets },
+    Migration { version: 6, name: "login_throttling", up: login_throttling },
+    Migration { version: 7, name: "two_factor", up: two_factor },
+    Migration { version: 8, name: "account_status", up: account_status },
+    Migration { version: 9, name: "session_history", up: session_history },
+    Migration { version: 10, name: "external_identities", up: external_identities },
+    Migration { version: 11, name: "post_search", up: post_search },
+    Migration { version: 12, name: "post_lists", up: post_lists },
+    Migration { version: 13, name: "post_questions", up: post_questions },
+    Migration { version: 14, name: "post_views", up: post_views },
+    Migration { version: 15, name: "identity_linking", up: identity_linking },
+];
+
+#[derive(Debug)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: i64,
+}
+
+fn ensure_migrations_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn applied(conn: &Connection) -> rusqlite::Result<Vec<AppliedMigration>> {
+    ensure_migrations_table(conn)?;
+    let mut stmt = conn.prepare("SELECT version, name, applied_at FROM schema_migrations ORDER BY version")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(AppliedMigration {
+            version: row.get(0)?,
+            name: row.get(1)?,
+            applied_at: row.get(2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn current_version(conn: &Connection) -> rusqlite::Result<i64> {
+    ensure_migrations_table(conn)?;
+    let version: Option<i64> = conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", [], |row| row.get(0))
+        .optional()?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+// Applies every migration newer than the database's current version, in order.
+// A failing migration rolls back on its own and stops the run, leaving earlier ones applied.
+pub fn run_pending(conn: &mut Connection) -> rusqlite::Result<Vec<&'static Migration>> {
+    let current = current_version(conn)?;
+    let mut ran = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![migration.version, migration.name, chrono::Utc::now().timestamp()],
+        )?;
+        tx.commit()?;
+        println!("[LOG] Applied migration {} ({})", migration.version, migration.name);
+        ran.push(migration);
+    }
+
+    Ok(ran)
+}
+
+// The tables `init_database` used to create. IF NOT EXISTS keeps this a no-op on
+// databases that predate the migration runner.
+fn baseline_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            sender_id INTEGER NOT NULL,
+            receiver_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+            read BOOLEAN DEFAULT FALSE,
+            message_type TEXT,
+            file_url TEXT,
+            FOREIGN KEY (sender_id) REFERENCES accounts(id),
+            FOREIGN KEY (receiver_id) REFERENCES accounts(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS accounts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            email VARCHAR(255) NOT NULL UNIQUE,
+            password VARCHAR(255) NOT NULL,
+            unique_id VARCHAR(255) NOT NULL UNIQUE,
+            first_name VARCHAR(255) NOT NULL,
+            last_name VARCHAR(255) NOT NULL,
+            account_type VARCHAR(50) NOT NULL,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_login DATETIME,
+            status VARCHAR(50) NOT NULL DEFAULT 'active',
+            profile TEXT NOT NULL,
+            CONSTRAINT email_unique UNIQUE (email),
+            CONSTRAINT uuid_unique UNIQUE (unique_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS posts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            description TEXT NOT NULL,
+            tags TEXT NOT NULL,
+            documents TEXT NOT NULL,
+            tips TEXT NOT NULL,
+            skills TEXT NOT NULL,
+            experience TEXT NOT NULL,
+            jobtype TEXT NOT NULL,
+            location TEXT NOT NULL,
+            date TEXT NOT NULL,
+            questions TEXT NOT NULL,
+            company_name TEXT NOT NULL,
+            employer_id TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'Pending' CHECK (status IN ('Accepted', 'Pending')),
+            FOREIGN KEY (employer_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS applications (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            post_id INTEGER NOT NULL,
+            applicant_id TEXT NOT NULL,
+            employer_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            answers TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (post_id) REFERENCES posts (id),
+            FOREIGN KEY (applicant_id) REFERENCES accounts (unique_id),
+            FOREIGN KEY (employer_id) REFERENCES accounts (unique_id)
+        );"
+    )
+}
+
+fn refresh_tokens(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS refresh_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE,
+            family_id TEXT NOT NULL,
+            account_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            rotated_at INTEGER,
+            revoked_at INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );"
+    )
+}
+
+// SQLite can't alter a CHECK constraint in place, so posts is rebuilt with the
+// widened status list and the rows copied across.
+fn posts_rejected_status(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE posts_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            description TEXT NOT NULL,
+            tags TEXT NOT NULL,
+            documents TEXT NOT NULL,
+            tips TEXT NOT NULL,
+            skills TEXT NOT NULL,
+            experience TEXT NOT NULL,
+            jobtype TEXT NOT NULL,
+            location TEXT NOT NULL,
+            date TEXT NOT NULL,
+            questions TEXT NOT NULL,
+            company_name TEXT NOT NULL,
+            employer_id TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'Pending' CHECK (status IN ('Accepted', 'Pending', 'Rejected')),
+            FOREIGN KEY (employer_id) REFERENCES accounts (unique_id)
+        );
+
+        INSERT INTO posts_new (id, title, description, tags, documents, tips, skills, experience,
+                               jobtype, location, date, questions, company_name, employer_id, status)
+        SELECT id, title, description, tags, documents, tips, skills, experience,
+               jobtype, location, date, questions, company_name, employer_id, status
+        FROM posts;
+
+        DROP TABLE posts;
+        ALTER TABLE posts_new RENAME TO posts;"
+    )
+}
+
+// Accounts that existed before verification keep their status; only new
+// registrations start out as pending_verification.
+fn email_verification(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE accounts ADD COLUMN email_verified_at INTEGER;
+
+        CREATE TABLE IF NOT EXISTS email_verifications (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE,
+            account_id TEXT NOT NULL,
+            email TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            consumed_at INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS email_verifications_account ON email_verifications (account_id);"
+    )
+}
+
+fn password_resets(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS password_resets (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE,
+            account_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            consumed_at INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS password_resets_account ON password_resets (account_id);"
+    )
+}
+
+fn login_throttling(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS login_failures (
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            failures INTEGER NOT NULL,
+            last_failure_at INTEGER NOT NULL,
+            locked_until INTEGER,
+            PRIMARY KEY (scope, key)
+        );
+
+        CREATE TABLE IF NOT EXISTS lockout_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            failures INTEGER NOT NULL,
+            locked_until INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
+        );"
+    )
+}
+
+fn two_factor(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS account_two_factor (
+            account_id TEXT PRIMARY KEY,
+            secret TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            confirmed_at INTEGER,
+            last_used_step INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id TEXT NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS recovery_codes_account ON recovery_codes (account_id);
+
+        CREATE TABLE IF NOT EXISTS login_challenges (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE,
+            account_id TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            consumed_at INTEGER,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS two_factor_policy (
+            account_type TEXT PRIMARY KEY,
+            required BOOLEAN NOT NULL
+        );"
+    )
+}
+
+// Accounts grandfathered past verification in migration 4 are recorded as verified as of
+// their creation, so reactivating or reinstating them never sends them back to pending.
+fn account_status(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE accounts ADD COLUMN status_reason TEXT;
+        ALTER TABLE accounts ADD COLUMN status_changed_at INTEGER;
+
+        UPDATE accounts
+        SET email_verified_at = COALESCE(CAST(strftime('%s', created_at) AS INTEGER), CAST(strftime('%s', 'now') AS INTEGER))
+        WHERE email_verified_at IS NULL AND status != 'pending_verification';"
+    )
+}
+
+// Each refresh token remembers the client it was handed to, so the live token of a family
+// says where that session was last used from.
+fn session_history(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE refresh_tokens ADD COLUMN ip TEXT;
+        ALTER TABLE refresh_tokens ADD COLUMN user_agent TEXT;
+
+        CREATE INDEX IF NOT EXISTS refresh_tokens_account ON refresh_tokens (account_id);
+
+        CREATE TABLE IF NOT EXISTS login_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id TEXT NOT NULL,
+            ip TEXT NOT NULL,
+            user_agent TEXT,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS login_events_account ON login_events (account_id);"
+    )
+}
+
+fn external_identities(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS external_identities (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            email TEXT,
+            created_at INTEGER NOT NULL,
+            last_login_at INTEGER,
+            UNIQUE (provider, subject),
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );
+
+        CREATE INDEX IF NOT EXISTS external_identities_account ON external_identities (account_id);
+
+        CREATE TABLE IF NOT EXISTS oidc_login_attempts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            state_hash TEXT NOT NULL UNIQUE,
+            provider TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            account_type TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            consumed_at INTEGER
+        );"
+    )
+}
+
+// Full-text index over the searchable post fields. It reads its text from `posts` (external
+// content), so the triggers only have to tell it which rows changed.
+fn post_search(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(
+            title, description, skills, tags,
+            content = 'posts', content_rowid = 'id', tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER IF NOT EXISTS posts_fts_insert AFTER INSERT ON posts BEGIN
+            INSERT INTO posts_fts (rowid, title, description, skills, tags)
+            VALUES (new.id, new.title, new.description, new.skills, new.tags);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS posts_fts_delete AFTER DELETE ON posts BEGIN
+            INSERT INTO posts_fts (posts_fts, rowid, title, description, skills, tags)
+            VALUES ('delete', old.id, old.title, old.description, old.skills, old.tags);
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS posts_fts_update AFTER UPDATE OF title, description, skills, tags ON posts BEGIN
+            INSERT INTO posts_fts (posts_fts, rowid, title, description, skills, tags)
+            VALUES ('delete', old.id, old.title, old.description, old.skills, old.tags);
+            INSERT INTO posts_fts (rowid, title, description, skills, tags)
+            VALUES (new.id, new.title, new.description, new.skills, new.tags);
+        END;
+
+        INSERT INTO posts_fts (posts_fts) VALUES ('rebuild');
+
+        CREATE INDEX IF NOT EXISTS posts_status_date ON posts (status, date, id);"
+    )
+}
+
+// Tags, skills and required documents get their own tables. Existing posts have them as
+// free text, which is split the way the API split text input when this was released.
+fn post_lists(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS post_tags (
+            post_id INTEGER NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (post_id, tag),
+            FOREIGN KEY (post_id) REFERENCES posts (id)
+        );
+
+        CREATE INDEX IF NOT EXISTS post_tags_tag ON post_tags (tag);
+
+        CREATE TABLE IF NOT EXISTS post_skills (
+            post_id INTEGER NOT NULL,
+            skill TEXT NOT NULL,
+            PRIMARY KEY (post_id, skill),
+            FOREIGN KEY (post_id) REFERENCES posts (id)
+        );
+
+        CREATE INDEX IF NOT EXISTS post_skills_skill ON post_skills (skill);
+
+        CREATE TABLE IF NOT EXISTS post_required_documents (
+            post_id INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            PRIMARY KEY (post_id, position),
+            FOREIGN KEY (post_id) REFERENCES posts (id)
+        );"
+    )?;
+
+    let posts: Vec<(i64, String, String, String)> = {
+        let mut stmt = tx.prepare("SELECT id, tags, skills, documents FROM posts")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for (id, tags, skills, documents) in posts {
+        let tags = post_lists_labels(&tags);
+        let skills = post_lists_labels(&skills);
+        let documents = post_lists_entries(&documents, |name| name.trim().to_string());
+        for tag in &tags {
+            tx.execute("INSERT INTO post_tags (post_id, tag) VALUES (?1, ?2)", params![id, tag])?;
+        }
+        for skill in &skills {
+            tx.execute("INSERT INTO post_skills (post_id, skill) VALUES (?1, ?2)", params![id, skill])?;
+        }
+        for (position, name) in documents.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO post_required_documents (post_id, position, name) VALUES (?1, ?2, ?3)",
+                params![id, position as i64, name],
+            )?;
+        }
+        tx.execute(
+            "UPDATE posts SET tags = ?1, skills = ?2, documents = ?3 WHERE id = ?4",
+            params![tags.join(", "), skills.join(", "), documents.join(", "), id],
+        )?;
+    }
+    Ok(())
+}
+
+// Copies of the list parsing in `repository::posts` as of migration 12, so later changes
+// there can't change what this migration does.
+fn post_lists_labels(raw: &str) -> Vec<String> {
+    post_lists_entries(raw, |label| label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+}
+
+// Splits "rust, sql" or a JSON array in a string, then normalizes each entry and drops
+// blanks and repeats, keeping the original order.
+fn post_lists_entries(raw: &str, normalize: impl Fn(&str) -> String) -> Vec<String> {
+    let raw = raw.trim();
+    let entries = match raw.starts_with('[') {
+        true => serde_json::from_str::<Vec<String>>(raw).ok(),
+        false => None,
+    }.unwrap_or_else(|| raw.split([',', ';', '\n']).map(str::to_string).collect());
+
+    let mut seen = std::collections::HashSet::new();
+    entries.iter()
+        .map(|entry| normalize(entry))
+        .filter(|entry| !entry.is_empty() && seen.insert(entry.clone()))
+        .collect()
+}
+
+// Rewrites every post's questions as a JSON array in the `questions::Question` format.
+fn post_questions(tx: &Transaction) -> rusqlite::Result<()> {
+    let posts: Vec<(i64, String)> = {
+        let mut stmt = tx.prepare("SELECT id, questions FROM posts")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for (id, raw) in posts {
+        let form = post_questions_from_legacy(&raw);
+        if form.is_empty() && !matches!(raw.trim(), "" | "[]") {
+            println!("[WARN] Could not read the questions of post {}, its form is now empty", id);
+        }
+        let form = serde_json::to_string(&form).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        tx.execute("UPDATE posts SET questions = ?1 WHERE id = ?2", params![form, id])?;
+    }
+    Ok(())
+}
+
+// Best-effort reading of the free-form questions stored before forms had a schema, as of
+// migration 13: a form that already has the new shape is kept, question texts become
+// optional long-text questions and anything else is dropped.
+fn post_questions_from_legacy(raw: &str) -> Vec<Value> {
+    let raw = raw.trim();
+    let entries = match serde_json::from_str::<Value>(raw) {
+        Ok(Value::Array(entries)) => entries,
+        Ok(_) => return Vec::new(),
+        Err(_) => raw.lines().map(|line| Value::String(line.to_string())).collect(),
+    };
+    if !entries.is_empty() && entries.iter().all(|entry| entry.get("id").is_some() && entry.get("type").is_some()) {
+        return entries;
+    }
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Value::String(text) => Some(text.as_str()),
+            Value::Object(fields) => ["label", "question", "text", "title"]
+                .iter()
+                .find_map(|key| fields.get(*key).and_then(Value::as_str)),
+            _ => None,
+        })
+        .map(|label| label.trim().chars().take(500).collect::<String>())
+        .filter(|label| !label.is_empty())
+        .enumerate()
+        .map(|(index, label)| serde_json::json!({
+            "id": format!("q{}", index + 1),
+            "type": "long_text",
+            "label": label,
+            "required": false
+        }))
+        .collect()
+}
+
+// One row per post and viewer, so repeat visits aren't counted twice. Signed-in viewers are
+// keyed by account, anonymous ones by a digest of their address and user agent.
+fn post_views(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS post_views (
+            post_id INTEGER NOT NULL,
+            viewer TEXT NOT NULL,
+            viewed_at INTEGER NOT NULL,
+            PRIMARY KEY (post_id, viewer),
+            FOREIGN KEY (post_id) REFERENCES posts (id)
+        );"
+    )
+}
+
+// Provider sign-ins started from a signed-in session, to link an identity or to confirm who
+// is at the keyboard, remember whose session started them.
+fn identity_linking(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE oidc_login_attempts ADD COLUMN purpose TEXT NOT NULL DEFAULT 'sign_in';
+        ALTER TABLE oidc_login_attempts ADD COLUMN account_id TEXT;
+
+        CREATE TABLE IF NOT EXISTS reauthentications (
+            session_id TEXT PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            authenticated_at INTEGER NOT NULL,
+            FOREIGN KEY (account_id) REFERENCES accounts (unique_id)
+        );"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_database_reaches_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let ran = run_pending(&mut conn).unwrap();
+        assert_eq!(ran.len(), MIGRATIONS.len());
+        assert_eq!(current_version(&conn).unwrap(), MIGRATIONS.last().unwrap().version);
+        assert!(run_pending(&mut conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn legacy_lists_are_split_and_cleaned() {
+        assert_eq!(post_lists_labels("Rust,  rust ; Remote   Work\n"), vec!["rust", "remote work"]);
+        assert_eq!(post_lists_labels(r#"["SQL", " sql ", ""]"#), vec!["sql"]);
+        assert_eq!(post_lists_entries("[broken, Resume", |name| name.trim().to_string()), vec!["[broken", "Resume"]);
+    }
+
+    #[test]
+    fn legacy_questions_become_long_text() {
+        let form = post_questions_from_legacy(r#"["Why us?", {"question": "Start date?"}, 3, "  "]"#);
+        assert_eq!(form, vec![
+            serde_json::json!({ "id": "q1", "type": "long_text", "label": "Why us?", "required": false }),
+            serde_json::json!({ "id": "q2", "type": "long_text", "label": "Start date?", "required": false }),
+        ]);
+        assert_eq!(post_questions_from_legacy("First\nSecond").len(), 2);
+        assert!(post_questions_from_legacy("{}").is_empty());
+
+        let current = r#"[{"id": "a", "type": "yes_no", "label": "Ok?"}]"#;
+        assert_eq!(post_questions_from_legacy(current), serde_json::from_str::<Vec<Value>>(current).unwrap());
+    }
+
+    #[test]
+    fn versions_are_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|pair| pair[0].version < pair[1].version));
+    }
+}
@@ -1,11 +1,9 @@
 use uuid::Uuid;
 
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use serde::Serialize;
 use serde::Deserialize;
-
-use crate::enc;
-
-use chrono;
+use validator::{Validate, ValidationError};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NewUser {
@@ -15,46 +13,192 @@ pub struct NewUser {
     pub profile: ProfileInfo,
     pub first_name: String,
     pub last_name: String,
-    pub account_type: String,
+    pub account_type: AccountType,
     pub created_at: String,
     pub last_login: String,
-    pub status: String, // "active", "inactive", "suspended"
+    pub status: AccountStatus,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Stored in `accounts.account_type` as the lowercase name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountType {
+    Student,
+    Employer,
+    Administrator
+}
+
+// Stored in `accounts.status`. New registrations wait in `pending_verification`
+// until the owner confirms their email address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    PendingVerification,
+    Inactive,
+    Suspended
+}
+
+// Stored as JSON in `accounts.profile`. The owner edits it section by section through
+// `PATCH /api/v1/user/profile`; `forms` are set through the student and employer form
+// endpoints and `tasks` are maintained by the server.
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct ProfileInfo {
-    pfp: String,
-    forms: Forms,
+    #[validate(url, length(max = 2048))]
+    pub pfp: String,
+    pub forms: Forms,
+    // Unused: remaining tasks are computed by `onboarding::checklist` rather than stored.
     tasks: Tasks,
+    #[validate(length(max = 2000))]
     bio: String,
+    #[validate(nested)]
     contact: ContactInfo,
+    #[validate(nested)]
     preferences: UserPreferences,
+    #[validate(nested)]
     education: Option<Education>,
+    #[validate(nested)]
     work_experience: Option<WorkExperience>,
+    #[validate(length(max = 50), custom(function = "short_entries"))]
     skills: Vec<String>,
+    #[validate(length(max = 50), custom(function = "short_entries"))]
     certifications: Vec<String>
 }
 
+// `PrivacySettings::profile_visibility`: who may look the account up at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    Public,
+    ConnectionsOnly,
+    Private
+}
+
+impl Visibility {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(Visibility::Public),
+            "connections-only" => Some(Visibility::ConnectionsOnly),
+            "private" => Some(Visibility::Private),
+            _ => None
+        }
+    }
+}
+
+// What other people are shown of a profile. Address, zip code, emergency contact and
+// preferences stay with the owner; email and phone are added per `show_email`/`show_phone`.
+#[derive(Debug, Serialize)]
+pub struct PublicProfile<'a> {
+    pfp: &'a str,
+    bio: &'a str,
+    city: &'a str,
+    state: &'a str,
+    country: &'a str,
+    education: Option<&'a Education>,
+    work_experience: Option<&'a WorkExperience>,
+    skills: &'a [String],
+    certifications: &'a [String]
+}
+
+impl ProfileInfo {
+    // Top-level keys a profile patch may not touch.
+    pub const READ_ONLY_SECTIONS: [&'static str; 2] = ["forms", "tasks"];
+
+    // Unrecognised values count as private.
+    pub fn visibility(&self) -> Visibility {
+        Visibility::parse(&self.preferences.privacy_settings.profile_visibility).unwrap_or(Visibility::Private)
+    }
+
+    pub fn shows_email(&self) -> bool {
+        self.preferences.privacy_settings.show_email
+    }
+
+    pub fn shows_phone(&self) -> bool {
+        self.preferences.privacy_settings.show_phone
+    }
+
+    pub fn phone(&self) -> &str {
+        &self.contact.phone
+    }
+
+    // Enough for someone else to know who they're dealing with.
+    pub fn has_basics(&self) -> bool {
+        !self.bio.trim().is_empty() && !self.contact.city.trim().is_empty()
+    }
+
+    pub fn has_background(&self) -> bool {
+        self.education.is_some() || self.work_experience.is_some() || !self.skills.is_empty()
+    }
+
+    pub fn public_view(&self) -> PublicProfile<'_> {
+        PublicProfile {
+            pfp: &self.pfp,
+            bio: &self.bio,
+            city: &self.contact.city,
+            state: &self.contact.state,
+            country: &self.contact.country,
+            education: self.education.as_ref(),
+            work_experience: self.work_experience.as_ref(),
+            skills: &self.skills,
+            certifications: &self.certifications,
+        }
+    }
+}
+
+// List entries (skills, achievements, ...) are short labels, not paragraphs.
+fn short_entries(entries: &[String]) -> Result<(), ValidationError> {
+    match entries.iter().all(|entry| !entry.trim().is_empty() && entry.chars().count() <= 100) {
+        true => Ok(()),
+        false => Err(ValidationError::new("entries").with_message("entries must be between 1 and 100 characters".into())),
+    }
+}
+
+fn one_of(value: &str, allowed: &[&str]) -> Result<(), ValidationError> {
+    match allowed.contains(&value) {
+        true => Ok(()),
+        false => Err(ValidationError::new("one_of").with_message(format!("must be one of: {}", allowed.join(", ")).into())),
+    }
+}
+
+fn profile_visibility(value: &str) -> Result<(), ValidationError> {
+    one_of(value, &["public", "private", "connections-only"])
+}
+
+fn remote_preference(value: &str) -> Result<(), ValidationError> {
+    one_of(value, &["remote", "hybrid", "on-site"])
+}
+
+fn job_types(values: &[String]) -> Result<(), ValidationError> {
+    values.iter().try_for_each(|value| one_of(value, &["full-time", "part-time", "internship"]))
+}
+
+// Dates are kept as the `YYYY-MM-DD` strings the frontend's date inputs produce.
+fn date(value: &str) -> Result<(), ValidationError> {
+    match chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(_) => Ok(()),
+        Err(_) => Err(ValidationError::new("date").with_message("must be a date formatted YYYY-MM-DD".into())),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Forms {
-    student: StudentForms,
-    employer: EmployerForms
+    pub student: StudentForms,
+    pub employer: EmployerForms
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StudentForms {
-    resume: bool,
-    transcript: bool,
-    agreement: bool,
-    background_check: bool
+    pub resume: bool,
+    pub transcript: bool,
+    pub agreement: bool,
+    pub background_check: bool
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EmployerForms {
-    employer_agreement: bool,
-    job_posting_guidelines: bool,
-    insurance_certificate: bool,
-    benefits_description: bool
+    pub employer_agreement: bool,
+    pub job_posting_guidelines: bool,
+    pub insurance_certificate: bool,
+    pub benefits_description: bool
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -63,28 +207,40 @@ pub struct Tasks {
     employer: Vec<String>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct ContactInfo {
+    #[validate(length(max = 32))]
     phone: String,
+    #[validate(length(max = 200))]
     address: String,
+    #[validate(length(max = 100))]
     city: String,
+    #[validate(length(max = 100))]
     state: String,
+    #[validate(length(max = 20))]
     zip: String,
+    #[validate(length(max = 100))]
     country: String,
+    #[validate(nested)]
     emergency_contact: Option<EmergencyContact>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct EmergencyContact {
+    #[validate(length(min = 1, max = 100))]
     name: String,
+    #[validate(length(max = 50))]
     relationship: String,
+    #[validate(length(min = 1, max = 32))]
     phone: String
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct UserPreferences {
     notification_settings: NotificationSettings,
+    #[validate(nested)]
     privacy_settings: PrivacySettings,
+    #[validate(nested)]
     job_preferences: Option<JobPreferences>
 }
 
@@ -95,53 +251,82 @@ pub struct NotificationSettings {
     sms_notifications: bool
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct PrivacySettings {
+    #[validate(custom(function = "profile_visibility"))]
     profile_visibility: String, // "public", "private", "connections-only"
     show_email: bool,
     show_phone: bool
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct Education {
+    #[validate(length(min = 1, max = 200))]
     institution: String,
+    #[validate(length(max = 100))]
     degree: String,
+    #[validate(length(max = 100))]
     field_of_study: String,
+    #[validate(custom(function = "date"))]
     start_date: String,
+    #[validate(custom(function = "date"))]
     end_date: Option<String>,
+    #[validate(range(min = 0.0, max = 5.0))]
     gpa: Option<f32>,
+    #[validate(length(max = 20), custom(function = "short_entries"))]
     achievements: Vec<String>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct WorkExperience {
+    #[validate(length(min = 1, max = 200))]
     company: String,
+    #[validate(length(min = 1, max = 100))]
     position: String,
+    #[validate(length(max = 100))]
     location: String,
+    #[validate(custom(function = "date"))]
     start_date: String,
+    #[validate(custom(function = "date"))]
     end_date: Option<String>,
+    #[validate(length(max = 20), custom(function = "short_entries"))]
     responsibilities: Vec<String>
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
 pub struct JobPreferences {
+    #[validate(length(max = 20), custom(function = "short_entries"))]
     desired_position: Vec<String>,
+    #[validate(length(max = 20), custom(function = "short_entries"))]
     desired_location: Vec<String>,
+    #[validate(nested)]
     salary_range: SalaryRange,
+    #[validate(custom(function = "job_types"))]
     job_type: Vec<String>, // ["full-time", "part-time", "internship"]
+    #[validate(custom(function = "remote_preference"))]
     remote_preference: String // "remote", "hybrid", "on-site"
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[validate(schema(function = "salary_bounds"))]
 pub struct SalaryRange {
+    #[validate(range(min = 0))]
     minimum: i32,
+    #[validate(range(min = 0))]
     maximum: i32,
+    #[validate(length(equal = 3))]
     currency: String
 }
 
+fn salary_bounds(range: &SalaryRange) -> Result<(), ValidationError> {
+    match range.minimum <= range.maximum {
+        true => Ok(()),
+        false => Err(ValidationError::new("salary_range").with_message("minimum must not exceed maximum".into())),
+    }
+}
+
 impl NewUser {
-    pub fn new(email: String, password: String, first_name: String, last_name: String, account_type: String) -> NewUser {
-        let hashed_password = enc::hash_password(password.as_str());
+    pub fn new(email: String, password_hash: String, first_name: String, last_name: String, account_type: AccountType) -> NewUser {
         let uuid = Uuid::new_v4().to_string();
         let current_time = chrono::Utc::now().to_rfc3339();
         
@@ -181,7 +366,7 @@ impl NewUser {
 
         NewUser {
             email,
-            password: hashed_password,
+            password: password_hash,
             unique_id: uuid,
             profile,
             first_name,
@@ -189,85 +374,84 @@ impl NewUser {
             account_type,
             created_at: current_time.clone(),
             last_login: current_time,
-            status: "active".to_string()
+            status: AccountStatus::PendingVerification
         }
     }
+}
 
-    pub fn dump(&self) -> rusqlite::Result<()> {
-        let conn = rusqlite::Connection::open("fbla.db")?;
+impl AccountType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccountType::Student => "student",
+            AccountType::Employer => "employer",
+            AccountType::Administrator => "administrator"
+        }
+    }
 
-        let p = serde_json::to_string(&self.profile).unwrap();
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "student" => Some(AccountType::Student),
+            "employer" => Some(AccountType::Employer),
+            "administrator" => Some(AccountType::Administrator),
+            _ => None
+        }
+    }
 
-        conn.execute(
-            "INSERT INTO accounts (email, password, unique_id, profile, first_name, last_name, account_type) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            rusqlite::params![self.email, self.password, self.unique_id, p, self.first_name, self.last_name, self.account_type],
-        )?;
-    
-        Ok(())
+    // Administrators are only ever provisioned by another administrator or the operator.
+    pub fn is_self_service(self) -> bool {
+        self != AccountType::Administrator
     }
+}
 
-    pub fn get_by_uuid(uuid: &str) -> rusqlite::Result<NewUser> {
-        let conn = rusqlite::Connection::open("fbla.db")?;
-        let mut stmt = conn.prepare_cached("SELECT email, password, unique_id, profile, first_name, last_name, account_type FROM accounts WHERE unique_id = ?1")?;
-        
-        stmt.query_row(rusqlite::params![uuid], |row| {
-            Ok(NewUser {
-                email: row.get(0)?,
-                password: row.get(1)?,
-                unique_id: row.get(2)?,
-                profile: serde_json::from_str(&row.get::<_, String>(3)?).map_err(|e| {
-                    println!("[ERROR] Failed to parse profile JSON: {}", e);
-                    rusqlite::Error::InvalidQuery
-                })?,
-                first_name: row.get(4)?,
-                last_name: row.get(5)?,
-                account_type: row.get(6)?,
-                created_at: String::new(),
-                last_login: String::new(),
-                status: String::new()
-            })
-        })
+impl ToSql for AccountType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
     }
+}
 
-    pub fn get_by_email(email: &str) -> rusqlite::Result<Option<NewUser>> {
-        println!("[LOG] Attempting to open database connection");
-        let conn = rusqlite::Connection::open("fbla.db")?;
-        println!("[LOG] Preparing SQL query for email: {}", email);
-        let mut stmt = conn.prepare("SELECT email, password, unique_id, profile, first_name, last_name, account_type FROM accounts WHERE email = ?1")?;
-        
-        println!("[LOG] Executing query for user with email: {}", email);
-        let user = stmt.query_row(rusqlite::params![email], |row| {
-            println!("[LOG] Processing row data for user: {}", email);
-            Ok(NewUser {
-                email: row.get(0)?,
-                password: row.get(1)?,
-                unique_id: row.get(2)?,
-                profile: serde_json::from_str(&row.get::<_, String>(3)?).map_err(|e| {
-                    println!("[ERROR] Failed to parse profile JSON: {}", e);
-                    rusqlite::Error::InvalidQuery
-                })?,
-                first_name: row.get(4)?,
-                last_name: row.get(5)?,
-                account_type: row.get(6)?,
-                created_at: String::new(),
-                last_login: String::new(),
-                status: String::new()
-            })
-        });
-
-        match user {
-            Ok(user) => {
-                println!("[LOG] Successfully retrieved user with email: {}", email);
-                Ok(Some(user))
-            },
-            Err(e) => {
-                println!("[WARN] No user found with email: {}, error: {}", email, e);
-                Ok(None)
-            }
+impl FromSql for AccountType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let value = value.as_str()?;
+        AccountType::parse(value)
+            .ok_or_else(|| FromSqlError::Other(format!("unknown account type: {}", value).into()))
+    }
+}
+
+impl AccountStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::PendingVerification => "pending_verification",
+            AccountStatus::Inactive => "inactive",
+            AccountStatus::Suspended => "suspended"
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(AccountStatus::Active),
+            "pending_verification" => Some(AccountStatus::PendingVerification),
+            "inactive" => Some(AccountStatus::Inactive),
+            "suspended" => Some(AccountStatus::Suspended),
+            _ => None
         }
     }
 }
 
+impl ToSql for AccountStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for AccountStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let value = value.as_str()?;
+        AccountStatus::parse(value)
+            .ok_or_else(|| FromSqlError::Other(format!("unknown account status: {}", value).into()))
+    }
+}
+
 impl Forms {
     pub fn new() -> Self {
         Forms {
@@ -287,6 +471,22 @@ impl Forms {
     }
 }
 
+impl StudentForms {
+    pub fn is_complete(&self) -> bool {
+        self.resume && self.transcript && self.agreement && self.background_check
+    }
+}
+
+impl EmployerForms {
+    // Employers must complete every form before they can post jobs.
+    pub fn is_complete(&self) -> bool {
+        self.employer_agreement
+            && self.job_posting_guidelines
+            && self.insurance_certificate
+            && self.benefits_description
+    }
+}
+
 impl Tasks {
     pub fn new() -> Self {
         Tasks {
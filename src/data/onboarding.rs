@@ -0,0 +1,110 @@
+use serde::Serialize;
+
+use crate::users::{AccountType, ProfileInfo};
+
+// What the checklist is computed from. Everything here is read fresh on each request, so the
+// checklist can never disagree with the profile it describes.
+pub struct Progress<'a> {
+    pub profile: &'a ProfileInfo,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
+    pub posts: i64,
+}
+
+struct Step {
+    key: &'static str,
+    label: &'static str,
+    done: fn(&Progress) -> bool,
+}
+
+const VERIFY_EMAIL: Step = Step {
+    key: "verify_email",
+    label: "Verify your email address",
+    done: |progress| progress.email_verified,
+};
+
+const STUDENT_STEPS: &[Step] = &[
+    VERIFY_EMAIL,
+    Step {
+        key: "complete_profile",
+        label: "Complete profile",
+        done: |progress| progress.profile.has_basics() && progress.profile.has_background(),
+    },
+    Step {
+        key: "upload_resume",
+        label: "Upload resume",
+        done: |progress| progress.profile.forms.student.resume,
+    },
+    Step {
+        key: "submit_forms",
+        label: "Submit required forms",
+        done: |progress| progress.profile.forms.student.is_complete(),
+    },
+];
+
+const EMPLOYER_STEPS: &[Step] = &[
+    VERIFY_EMAIL,
+    Step {
+        key: "complete_company_profile",
+        label: "Complete company profile",
+        done: |progress| progress.profile.has_basics(),
+    },
+    Step {
+        key: "submit_documentation",
+        label: "Submit required documentation",
+        done: |progress| progress.profile.forms.employer.is_complete(),
+    },
+    Step {
+        key: "post_jobs",
+        label: "Post job opportunities",
+        done: |progress| progress.posts > 0,
+    },
+];
+
+const ADMINISTRATOR_STEPS: &[Step] = &[
+    VERIFY_EMAIL,
+    Step {
+        key: "enable_two_factor",
+        label: "Set up two-factor authentication",
+        done: |progress| progress.two_factor_enabled,
+    },
+];
+
+fn steps(account_type: AccountType) -> &'static [Step] {
+    match account_type {
+        AccountType::Student => STUDENT_STEPS,
+        AccountType::Employer => EMPLOYER_STEPS,
+        AccountType::Administrator => ADMINISTRATOR_STEPS,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChecklistItem {
+    pub key: &'static str,
+    pub label: &'static str,
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Checklist {
+    pub items: Vec<ChecklistItem>,
+    pub completed: usize,
+    pub total: usize,
+    pub complete: bool,
+}
+
+impl Checklist {
+    // Labels of the steps still to do, in order.
+    pub fn remaining(&self) -> Vec<&'static str> {
+        self.items.iter().filter(|item| !item.complete).map(|item| item.label).collect()
+    }
+}
+
+pub fn checklist(account_type: AccountType, progress: &Progress) -> Checklist {
+    let items: Vec<ChecklistItem> = steps(account_type)
+        .iter()
+        .map(|step| ChecklistItem { key: step.key, label: step.label, complete: (step.done)(progress) })
+        .collect();
+    let completed = items.iter().filter(|item| item.complete).count();
+    Checklist { total: items.len(), complete: completed == items.len(), completed, items }
+}
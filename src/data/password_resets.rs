@@ -0,0 +1,83 @@
+use std::fmt;
+
+use rusqlite::{params, OptionalExtension};
+
+use crate::repository::AccountRepo;
+use crate::sessions;
+use crate::tokens;
+
+pub const RESET_TOKEN_TTL_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug)]
+pub enum ResetError {
+    Invalid,
+    Expired,
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for ResetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResetError::Invalid => write!(f, "Invalid password reset token"),
+            ResetError::Expired => write!(f, "Password reset token has expired"),
+            ResetError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ResetError {
+    fn from(e: rusqlite::Error) -> Self {
+        ResetError::Database(e)
+    }
+}
+
+// Only the newest reset link for an account works; requesting another consumes the rest.
+pub fn issue(conn: &rusqlite::Connection, account_id: &str) -> rusqlite::Result<String> {
+    let token = tokens::generate_opaque_token();
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE password_resets SET consumed_at = ?1 WHERE account_id = ?2 AND consumed_at IS NULL",
+        params![now, account_id],
+    )?;
+    conn.execute(
+        "INSERT INTO password_resets (token_hash, account_id, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![tokens::hash_opaque_token(&token), account_id, now, now + RESET_TOKEN_TTL_SECONDS],
+    )?;
+    Ok(token)
+}
+
+// Consumes the token, stores the new password hash and revokes every session of the
+// account, all or nothing. Returns the account's unique_id.
+pub fn consume(conn: &mut rusqlite::Connection, token: &str, password_hash: &str) -> Result<String, ResetError> {
+    let tx = conn.transaction()?;
+    let now = chrono::Utc::now().timestamp();
+
+    let stored = tx.query_row(
+        "SELECT id, account_id, expires_at, consumed_at FROM password_resets WHERE token_hash = ?1",
+        [tokens::hash_opaque_token(token)],
+        |row| Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, Option<i64>>(3)?,
+        )),
+    ).optional()?;
+
+    let (id, account_id, expires_at) = match stored {
+        Some((id, account_id, expires_at, None)) => (id, account_id, expires_at),
+        _ => return Err(ResetError::Invalid),
+    };
+    if expires_at <= now {
+        return Err(ResetError::Expired);
+    }
+
+    tx.execute("UPDATE password_resets SET consumed_at = ?1 WHERE id = ?2", params![now, id])?;
+    if AccountRepo::new(&tx).update_password(&account_id, password_hash)? == 0 {
+        return Err(ResetError::Invalid);
+    }
+    sessions::revoke_all(&tx, &account_id)?;
+    tx.commit()?;
+
+    Ok(account_id)
+}
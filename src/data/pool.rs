@@ -0,0 +1,66 @@
+use std::env;
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::web;
+use r2d2_sqlite::SqliteConnectionManager;
+
+pub type DbPool = r2d2::Pool<SqliteConnectionManager>;
+
+#[derive(Debug)]
+pub enum DbError {
+    Pool(r2d2::Error),
+    Query(rusqlite::Error),
+    Blocking,
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Pool(e) => write!(f, "Failed to get database connection: {}", e),
+            DbError::Query(e) => write!(f, "Database error: {}", e),
+            DbError::Blocking => write!(f, "Database task was cancelled"),
+        }
+    }
+}
+
+impl DbError {
+    // True when the query was rejected by a UNIQUE constraint, e.g. a duplicate email.
+    pub fn is_unique_violation(&self) -> bool {
+        matches!(
+            self,
+            DbError::Query(rusqlite::Error::SqliteFailure(e, _))
+                if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+        )
+    }
+}
+
+pub fn database_path() -> String {
+    env::var("DATABASE_PATH").unwrap_or_else(|_| "fbla.db".to_string())
+}
+
+// WAL lets readers proceed while a write is in flight, and the busy timeout makes
+// writers wait for the lock instead of failing immediately with SQLITE_BUSY.
+pub fn init_pool(path: &str) -> Result<DbPool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+    });
+    r2d2::Pool::builder().max_size(8).build(manager)
+}
+
+// Runs `f` with a pooled connection on actix's blocking thread pool so SQLite
+// never stalls an async worker.
+pub async fn run<F, T>(pool: &DbPool, f: F) -> Result<T, DbError>
+where
+    F: FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let mut conn = pool.get().map_err(DbError::Pool)?;
+        f(&mut conn).map_err(DbError::Query)
+    })
+    .await
+    .map_err(|_| DbError::Blocking)?
+}
@@ -0,0 +1,62 @@
+use std::collections::HashSet;
+
+use rusqlite::{params, Connection};
+
+use crate::users::{AccountStatus, Visibility};
+
+// Who is looking at an account, as far as its privacy settings are concerned.
+pub struct Viewer {
+    id: Option<i64>,
+    is_admin: bool,
+    connections: HashSet<i64>,
+}
+
+impl Viewer {
+    pub fn anonymous() -> Self {
+        Viewer { id: None, is_admin: false, connections: HashSet::new() }
+    }
+
+    pub fn for_account(conn: &Connection, id: i64, is_admin: bool) -> rusqlite::Result<Self> {
+        Ok(Viewer { id: Some(id), is_admin, connections: connections(conn, id)? })
+    }
+
+    // Owners and administrators see everything, including contact details the owner hides.
+    pub fn is_privileged(&self, subject_id: i64) -> bool {
+        self.is_admin || self.id == Some(subject_id)
+    }
+
+    // Deactivated and suspended accounts drop out of sight for everyone else.
+    pub fn can_see(&self, subject_id: i64, visibility: Visibility, status: AccountStatus) -> bool {
+        if self.is_privileged(subject_id) {
+            return true;
+        }
+        if matches!(status, AccountStatus::Inactive | AccountStatus::Suspended) {
+            return false;
+        }
+        match visibility {
+            Visibility::Public => true,
+            Visibility::ConnectionsOnly => self.connections.contains(&subject_id),
+            Visibility::Private => false,
+        }
+    }
+}
+
+// Accounts the given one is connected to: there is an application between them, either way
+// round, or a conversation both of them have written in. A message nobody answered doesn't
+// count, or writing to someone would be enough to see their profile.
+pub fn connections(conn: &Connection, account_id: i64) -> rusqlite::Result<HashSet<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.receiver_id FROM messages m
+         WHERE m.sender_id = ?1
+           AND EXISTS (SELECT 1 FROM messages r WHERE r.sender_id = m.receiver_id AND r.receiver_id = ?1)
+         UNION
+         SELECT other.id
+         FROM applications a
+         JOIN accounts me ON me.id = ?1
+         JOIN accounts other ON other.unique_id =
+             CASE WHEN a.applicant_id = me.unique_id THEN a.employer_id ELSE a.applicant_id END
+         WHERE a.applicant_id = me.unique_id OR a.employer_id = me.unique_id"
+    )?;
+    let ids = stmt.query_map(params![account_id], |row| row.get(0))?;
+    ids.collect()
+}
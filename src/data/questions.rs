@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use validator::{Validate, ValidationError};
+
+use crate::validation::FieldError;
+
+// Text answers are capped at these lengths unless the question sets its own `max_length`.
+const SHORT_TEXT_MAX: usize = 200;
+const LONG_TEXT_MAX: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    ShortText,
+    LongText,
+    SingleChoice,
+    MultiChoice,
+    YesNo,
+    // Answered with the `file_url` returned by `/api/v1/upload`.
+    File,
+}
+
+impl QuestionKind {
+    fn is_text(self) -> bool {
+        matches!(self, QuestionKind::ShortText | QuestionKind::LongText)
+    }
+
+    fn is_choice(self) -> bool {
+        matches!(self, QuestionKind::SingleChoice | QuestionKind::MultiChoice)
+    }
+}
+
+// One question on a post's application form. Answers are keyed by `id`.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+#[validate(schema(function = "question_shape"))]
+pub struct Question {
+    #[validate(length(min = 1, max = 64), custom(function = "question_id"))]
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: QuestionKind,
+    #[validate(length(min = 1, max = 500))]
+    pub label: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate(range(min = 1, max = 10000))]
+    pub max_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+fn question_id(id: &str) -> Result<(), ValidationError> {
+    match id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        true => Ok(()),
+        false => Err(ValidationError::new("question_id").with_message("may only contain letters, digits, '_' and '-'".into())),
+    }
+}
+
+// Choices need options and only choices have them; only text has a length limit.
+fn question_shape(question: &Question) -> Result<(), ValidationError> {
+    let invalid = |message: &'static str| Err(ValidationError::new("question").with_message(message.into()));
+    if question.kind.is_choice() {
+        let mut seen = HashSet::new();
+        if question.options.len() < 2 || question.options.len() > 50 {
+            return invalid("choice questions need between 2 and 50 options");
+        }
+        if !question.options.iter().all(|option| !option.trim().is_empty() && option.chars().count() <= 200 && seen.insert(option)) {
+            return invalid("options must be unique and between 1 and 200 characters");
+        }
+    } else if !question.options.is_empty() {
+        return invalid("only choice questions have options");
+    }
+    if question.max_length.is_some() && !question.kind.is_text() {
+        return invalid("only text questions have a max_length");
+    }
+    Ok(())
+}
+
+const MAX_QUESTIONS: usize = 50;
+
+// Rules for the form as a whole. Question ids identify answers, so they must be unique.
+pub fn check_form(questions: &[Question]) -> Result<(), ValidationError> {
+    let mut seen = HashSet::new();
+    if questions.len() > MAX_QUESTIONS {
+        return Err(ValidationError::new("questions").with_message(format!("a form has at most {} questions", MAX_QUESTIONS).into()));
+    }
+    match questions.iter().find(|question| !seen.insert(question.id.as_str())) {
+        Some(question) => Err(ValidationError::new("questions").with_message(format!("question id '{}' is used twice", question.id).into())),
+        None => Ok(()),
+    }
+}
+
+// Older clients sent the form as a JSON string; it is still accepted if it holds the same array.
+pub fn question_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Question>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a list of questions")]
+    enum Input {
+        List(Vec<Question>),
+        Text(String),
+    }
+    match Input::deserialize(deserializer)? {
+        Input::List(questions) => Ok(questions),
+        Input::Text(text) if text.trim().is_empty() => Ok(Vec::new()),
+        Input::Text(text) => serde_json::from_str(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+fn answer_error(id: &str, code: &str, message: String) -> FieldError {
+    FieldError {
+        field: format!("answers.{}", id),
+        code: code.to_string(),
+        message,
+    }
+}
+
+fn is_blank(answer: &Value) -> bool {
+    match answer {
+        Value::Null => true,
+        Value::String(text) => text.trim().is_empty(),
+        Value::Array(entries) => entries.is_empty(),
+        _ => false,
+    }
+}
+
+// The `file_url` of a file that was uploaded to `uploads_dir`: `/uploads/<uuid>.<extension>`,
+// optionally behind the server's address.
+fn is_upload_reference(answer: &str, uploads_dir: &Path) -> bool {
+    let Some((origin, name)) = answer.split_once("/uploads/") else {
+        return false;
+    };
+    let host = origin.strip_prefix("https://").or_else(|| origin.strip_prefix("http://")).unwrap_or(origin);
+    let Some((stem, extension)) = name.split_once('.') else {
+        return false;
+    };
+    answer.len() <= 500
+        && !host.contains('/')
+        && stem.len() == 36
+        && uuid::Uuid::parse_str(stem).is_ok()
+        && !extension.is_empty()
+        && !extension.contains(['/', '\\', '.'])
+        && uploads_dir.join(name).is_file()
+}
+
+fn check_answer(question: &Question, answer: &Value, uploads_dir: &Path) -> Option<FieldError> {
+    let id = &question.id;
+    match (question.kind, answer) {
+        (QuestionKind::ShortText | QuestionKind::LongText, Value::String(text)) => {
+            let default_max = if question.kind == QuestionKind::ShortText { SHORT_TEXT_MAX } else { LONG_TEXT_MAX };
+            let max = question.max_length.unwrap_or(default_max);
+            (text.chars().count() > max).then(|| answer_error(id, "length", format!("must be at most {} characters", max)))
+        },
+        (QuestionKind::SingleChoice, Value::String(choice)) => (!question.options.contains(choice))
+            .then(|| answer_error(id, "one_of", format!("must be one of: {}", question.options.join(", ")))),
+        (QuestionKind::MultiChoice, Value::Array(choices)) => {
+            let mut seen = HashSet::new();
+            let valid = choices.iter().all(|choice| match choice {
+                Value::String(choice) => question.options.contains(choice) && seen.insert(choice),
+                _ => false,
+            });
+            (!valid).then(|| answer_error(id, "one_of", format!("must be distinct choices from: {}", question.options.join(", "))))
+        },
+        (QuestionKind::YesNo, Value::Bool(_)) => None,
+        (QuestionKind::File, Value::String(reference)) => (!is_upload_reference(reference, uploads_dir))
+            .then(|| answer_error(id, "file", "must be a file_url returned by /api/v1/upload".to_string())),
+        (kind, _) => {
+            let expected = match kind {
+                QuestionKind::ShortText | QuestionKind::LongText | QuestionKind::SingleChoice => "a string",
+                QuestionKind::MultiChoice => "a list of strings",
+                QuestionKind::YesNo => "true or false",
+                QuestionKind::File => "a file_url",
+            };
+            Some(answer_error(id, "type", format!("must be {}", expected)))
+        },
+    }
+}
+
+// Checks submitted answers against the post's form and returns what gets stored: one entry per
+// answered question, with blank answers to optional questions left out. File answers have to
+// name a file in `uploads_dir`.
+pub fn check_answers(questions: &[Question], answers: &Map<String, Value>, uploads_dir: &Path) -> Result<Map<String, Value>, Vec<FieldError>> {
+    let mut errors: Vec<FieldError> = answers
+        .keys()
+        .filter(|id| !questions.iter().any(|question| &question.id == *id))
+        .map(|id| answer_error(id, "unknown_question", "is not a question on this post".to_string()))
+        .collect();
+
+    let mut accepted = Map::new();
+    for question in questions {
+        match answers.get(&question.id).filter(|answer| !is_blank(answer)) {
+            Some(answer) => match check_answer(question, answer, uploads_dir) {
+                Some(error) => errors.push(error),
+                None => {
+                    accepted.insert(question.id.clone(), answer.clone());
+                },
+            },
+            None if question.required => errors.push(answer_error(&question.id, "required", "is required".to_string())),
+            None => {},
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(accepted),
+        false => {
+            errors.sort_by(|a, b| a.field.cmp(&b.field));
+            Err(errors)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn form() -> Vec<Question> {
+        serde_json::from_value(json!([
+            { "id": "why", "type": "short_text", "label": "Why us?", "required": true, "max_length": 10 },
+            { "id": "shift", "type": "single_choice", "label": "Shift", "options": ["day", "night"] },
+            { "id": "days", "type": "multi_choice", "label": "Days", "options": ["mon", "tue", "wed"] },
+            { "id": "car", "type": "yes_no", "label": "Do you drive?" },
+            { "id": "resume", "type": "file", "label": "Resume" }
+        ])).unwrap()
+    }
+
+    fn answers(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn error_codes(result: Result<Map<String, Value>, Vec<FieldError>>) -> Vec<(String, String)> {
+        result.unwrap_err().into_iter().map(|error| (error.field, error.code)).collect()
+    }
+
+    #[test]
+    fn accepts_valid_answers_and_drops_blank_optional_ones() {
+        let uploads = tempfile::tempdir().unwrap();
+        let accepted = check_answers(&form(), &answers(json!({
+            "why": "I like it",
+            "shift": "night",
+            "days": ["mon", "wed"],
+            "car": false,
+            "resume": ""
+        })), uploads.path()).unwrap();
+        assert_eq!(accepted.len(), 4);
+        assert!(!accepted.contains_key("resume"));
+    }
+
+    #[test]
+    fn reports_every_problem_by_field() {
+        let uploads = tempfile::tempdir().unwrap();
+        let codes = error_codes(check_answers(&form(), &answers(json!({
+            "shift": "evening",
+            "days": ["mon", "mon"],
+            "car": "yes",
+            "resume": "not-a-file",
+            "extra": 1
+        })), uploads.path()));
+        assert_eq!(codes, vec![
+            ("answers.car".to_string(), "type".to_string()),
+            ("answers.days".to_string(), "one_of".to_string()),
+            ("answers.extra".to_string(), "unknown_question".to_string()),
+            ("answers.resume".to_string(), "file".to_string()),
+            ("answers.shift".to_string(), "one_of".to_string()),
+            ("answers.why".to_string(), "required".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn text_answers_respect_max_length() {
+        let uploads = tempfile::tempdir().unwrap();
+        let codes = error_codes(check_answers(&form(), &answers(json!({ "why": "far too long an answer" })), uploads.path()));
+        assert_eq!(codes, vec![("answers.why".to_string(), "length".to_string())]);
+    }
+
+    #[test]
+    fn file_answers_must_name_an_existing_upload() {
+        let uploads = tempfile::tempdir().unwrap();
+        let name = format!("{}.pdf", uuid::Uuid::new_v4());
+        std::fs::write(uploads.path().join(&name), b"%PDF").unwrap();
+        let check = |answer: String| is_upload_reference(&answer, uploads.path());
+
+        assert!(check(format!("127.0.0.1:8080/uploads/{}", name)));
+        assert!(check(format!("https://example.com/uploads/{}", name)));
+        assert!(check(format!("/uploads/{}", name)));
+        assert!(!check(name.clone()));
+        assert!(!check(format!("https://example.com/elsewhere/uploads/{}", name)));
+        assert!(!check(format!("/uploads/{}.pdf", uuid::Uuid::new_v4())));
+        assert!(!check(format!("/uploads/../uploads/{}", name)));
+
+        let answers = answers(json!({ "why": "Because", "resume": format!("/uploads/{}", name) }));
+        assert!(check_answers(&form(), &answers, uploads.path()).unwrap().contains_key("resume"));
+    }
+
+    #[test]
+    fn form_rules() {
+        let mut questions = form();
+        assert!(questions.iter().all(|question| question.validate().is_ok()));
+        assert!(check_form(&questions).is_ok());
+
+        questions[1].options.pop();
+        assert!(questions[1].validate().is_err());
+        questions.push(questions[0].clone());
+        assert!(check_form(&questions).is_err());
+    }
+}
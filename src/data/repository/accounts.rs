@@ -0,0 +1,315 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::users::{AccountStatus, AccountType, NewUser, ProfileInfo, Visibility};
+
+use super::json_column;
+
+#[derive(Debug)]
+pub struct Account {
+    pub id: i64,
+    pub email: String,
+    pub password: String, // argon2 hash
+    pub unique_id: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub account_type: AccountType,
+    pub status: AccountStatus,
+    // Why an administrator suspended the account; shown to its owner when they are turned away.
+    pub status_reason: Option<String>,
+    pub profile: ProfileInfo,
+    pub created_at: String,
+    pub last_login: Option<String>,
+    pub email_verified_at: Option<i64>,
+}
+
+// What any visitor may see about an account, provided its privacy settings let them
+// (see `privacy::Viewer`).
+#[derive(Debug, Serialize)]
+pub struct PublicAccount {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: String,
+    pub pfp: String,
+    pub account_type: AccountType,
+    #[serde(skip)]
+    pub visibility: Visibility,
+    #[serde(skip)]
+    pub status: AccountStatus,
+}
+
+pub struct AccountRepo<'a> {
+    conn: &'a Connection,
+}
+
+const ACCOUNT_COLUMNS: &str =
+    "id, email, password, unique_id, first_name, last_name, account_type, status, profile, status_reason, created_at, last_login, email_verified_at";
+
+fn account_from_row(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        id: row.get(0)?,
+        email: row.get(1)?,
+        password: row.get(2)?,
+        unique_id: row.get(3)?,
+        first_name: row.get(4)?,
+        last_name: row.get(5)?,
+        account_type: row.get(6)?,
+        status: row.get(7)?,
+        profile: json_column(row, 8)?,
+        status_reason: row.get(9)?,
+        created_at: row.get(10)?,
+        last_login: row.get(11)?,
+        email_verified_at: row.get(12)?,
+    })
+}
+
+impl<'a> AccountRepo<'a> {
+    pub fn new(conn: &'a Connection) -> Self {
+        AccountRepo { conn }
+    }
+
+    pub fn insert(&self, user: &NewUser) -> rusqlite::Result<i64> {
+        let profile = serde_json::to_string(&user.profile)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            "INSERT INTO accounts (email, password, unique_id, profile, first_name, last_name, account_type, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![user.email, user.password, user.unique_id, profile, user.first_name, user.last_name, user.account_type, user.status],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn find_by_unique_id(&self, unique_id: &str) -> rusqlite::Result<Option<Account>> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE unique_id = ?1", ACCOUNT_COLUMNS),
+            [unique_id],
+            account_from_row,
+        ).optional()
+    }
+
+    pub fn find_by_email(&self, email: &str) -> rusqlite::Result<Option<Account>> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE email = ?1", ACCOUNT_COLUMNS),
+            [email],
+            account_from_row,
+        ).optional()
+    }
+
+    pub fn find_by_id(&self, id: i64) -> rusqlite::Result<Option<Account>> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE id = ?1", ACCOUNT_COLUMNS),
+            [id],
+            account_from_row,
+        ).optional()
+    }
+
+    // Counts every account, or only those of the given type.
+    pub fn count(&self, account_type: Option<AccountType>) -> rusqlite::Result<i64> {
+        match account_type {
+            Some(account_type) => self.conn.query_row(
+                "SELECT COUNT(*) FROM accounts WHERE account_type = ?1",
+                [account_type],
+                |row| row.get(0),
+            ),
+            None => self.conn.query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0)),
+        }
+    }
+
+    pub fn list_public(&self) -> rusqlite::Result<Vec<PublicAccount>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, first_name, last_name, COALESCE(json_extract(profile, '$.pfp'), ''), account_type,
+                    json_extract(profile, '$.preferences.privacy_settings.profile_visibility'), status
+             FROM accounts"
+        )?;
+        let accounts = stmt.query_map([], |row| {
+            Ok(PublicAccount {
+                id: row.get(0)?,
+                first_name: row.get(1)?,
+                last_name: row.get(2)?,
+                pfp: row.get(3)?,
+                account_type: row.get(4)?,
+                visibility: row.get::<_, Option<String>>(5)?
+                    .and_then(|visibility| Visibility::parse(&visibility))
+                    .unwrap_or(Visibility::Private),
+                status: row.get(6)?,
+            })
+        })?;
+        accounts.collect()
+    }
+
+    pub fn set_account_type(&self, id: i64, account_type: AccountType) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE accounts SET account_type = ?1 WHERE id = ?2",
+            params![account_type, id],
+        )
+    }
+
+    pub fn update_password(&self, unique_id: &str, password_hash: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE accounts SET password = ?1 WHERE unique_id = ?2",
+            params![password_hash, unique_id],
+        )
+    }
+
+    // Records `email` as the account's verified address, activating it if it was waiting on
+    // verification. When the address differs from the current one this is an email change.
+    pub fn confirm_email(&self, unique_id: &str, email: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE accounts SET
+                email = ?5,
+                email_verified_at = ?1,
+                status = CASE WHEN status = ?2 THEN ?3 ELSE status END
+             WHERE unique_id = ?4",
+            params![
+                chrono::Utc::now().timestamp(),
+                AccountStatus::PendingVerification,
+                AccountStatus::Active,
+                unique_id,
+                email
+            ],
+        )
+    }
+
+    pub fn record_login(&self, unique_id: &str) -> rusqlite::Result<usize> {
+        self.conn.execute("UPDATE accounts SET last_login = datetime('now') WHERE unique_id = ?1", [unique_id])
+    }
+
+    pub fn set_status(&self, unique_id: &str, status: AccountStatus, reason: Option<&str>) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE accounts SET status = ?1, status_reason = ?2, status_changed_at = ?3 WHERE unique_id = ?4",
+            params![status, reason, chrono::Utc::now().timestamp(), unique_id],
+        )
+    }
+
+    // Brings a deactivated or suspended account back. Accounts that never confirmed their
+    // email address go back to waiting on verification rather than becoming active.
+    pub fn reactivate(&self, unique_id: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE accounts SET
+                status = CASE WHEN email_verified_at IS NULL THEN ?1 ELSE ?2 END,
+                status_reason = NULL,
+                status_changed_at = ?3
+             WHERE unique_id = ?4",
+            params![
+                AccountStatus::PendingVerification,
+                AccountStatus::Active,
+                chrono::Utc::now().timestamp(),
+                unique_id
+            ],
+        )
+    }
+
+    // Permanently removes the account and everything hanging off it: its posts and every
+    // application to them, the applications it submitted, messages in either direction
+    // (the other participant loses the conversation too), the posts it viewed and all sign-in state.
+    // Callers run it inside a transaction.
+    pub fn delete(&self, unique_id: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM applications
+             WHERE applicant_id = ?1 OR employer_id = ?1
+                OR post_id IN (SELECT id FROM posts WHERE employer_id = ?1)",
+            [unique_id],
+        )?;
+        for table in ["post_tags", "post_skills", "post_required_documents", "post_views"] {
+            self.conn.execute(
+                &format!("DELETE FROM {} WHERE post_id IN (SELECT id FROM posts WHERE employer_id = ?1)", table),
+                [unique_id],
+            )?;
+        }
+        self.conn.execute("DELETE FROM posts WHERE employer_id = ?1", [unique_id])?;
+        // Views by a signed-in account are keyed by it (see `post_routes::viewer_key`).
+        self.conn.execute("DELETE FROM post_views WHERE viewer = 'account:' || ?1", [unique_id])?;
+        self.conn.execute(
+            "DELETE FROM messages
+             WHERE sender_id IN (SELECT id FROM accounts WHERE unique_id = ?1)
+                OR receiver_id IN (SELECT id FROM accounts WHERE unique_id = ?1)",
+            [unique_id],
+        )?;
+        for table in [
+            "refresh_tokens",
+            "email_verifications",
+            "password_resets",
+            "account_two_factor",
+            "recovery_codes",
+            "login_challenges",
+            "login_events",
+            "external_identities",
+            "oidc_login_attempts",
+            "reauthentications",
+        ] {
+            self.conn.execute(&format!("DELETE FROM {} WHERE account_id = ?1", table), [unique_id])?;
+        }
+        self.conn.execute("DELETE FROM accounts WHERE unique_id = ?1", [unique_id])
+    }
+
+    pub fn update_profile(&self, unique_id: &str, profile: &ProfileInfo) -> rusqlite::Result<()> {
+        let profile = serde_json::to_string(profile)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.execute(
+            "UPDATE accounts SET profile = ?1 WHERE unique_id = ?2",
+            params![profile, unique_id],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::repository::{NewPost, PostRepo};
+    use crate::users::AccountType;
+
+    fn employer(conn: &Connection) -> NewUser {
+        insert_account(conn, "owner@example.com", AccountType::Employer)
+    }
+
+    pub(crate) fn insert_account(conn: &Connection, email: &str, account_type: AccountType) -> NewUser {
+        let user = NewUser::new(email.to_string(), "hash".to_string(), "Ada".to_string(), "Lovelace".to_string(), account_type);
+        AccountRepo::new(conn).insert(&user).unwrap();
+        user
+    }
+
+    #[test]
+    fn finds_inserted_account() {
+        let conn = crate::repository::test_connection();
+        let user = employer(&conn);
+        let repo = AccountRepo::new(&conn);
+
+        let account = repo.find_by_email("owner@example.com").unwrap().unwrap();
+        assert_eq!(account.unique_id, user.unique_id);
+        assert_eq!(account.account_type, AccountType::Employer);
+        assert!(account.email_verified_at.is_none());
+        assert_eq!(repo.find_by_id(account.id).unwrap().unwrap().email, "owner@example.com");
+        assert!(repo.find_by_unique_id("missing").unwrap().is_none());
+        assert_eq!(repo.count(Some(AccountType::Employer)).unwrap(), 1);
+        assert_eq!(repo.count(Some(AccountType::Student)).unwrap(), 0);
+    }
+
+    #[test]
+    fn duplicate_email_is_rejected() {
+        let conn = crate::repository::test_connection();
+        employer(&conn);
+        let again = NewUser::new("owner@example.com".to_string(), "hash".to_string(), "B".to_string(), "C".to_string(), AccountType::Student);
+        assert!(AccountRepo::new(&conn).insert(&again).is_err());
+    }
+
+    #[test]
+    fn delete_removes_posts_and_views() {
+        let conn = crate::repository::test_connection();
+        let user = employer(&conn);
+        let posts = PostRepo::new(&conn);
+        let post = NewPost { title: "Intern".to_string(), date: "2026-01-01".to_string(), tags: vec!["Rust".to_string()], ..Default::default() };
+        let post_id = posts.insert(&user.unique_id, &post).unwrap();
+        let other = insert_account(&conn, "other@example.com", AccountType::Employer);
+        let other_id = posts.insert(&other.unique_id, &post).unwrap();
+        posts.record_view(other_id, &format!("account:{}", user.unique_id)).unwrap();
+
+        AccountRepo::new(&conn).delete(&user.unique_id).unwrap();
+
+        assert!(AccountRepo::new(&conn).find_by_unique_id(&user.unique_id).unwrap().is_none());
+        assert!(posts.find_by_id(post_id).unwrap().is_none());
+        assert_eq!(posts.view_count(other_id).unwrap(), 0);
+        let tags: i64 = conn.query_row("SELECT COUNT(*) FROM post_tags WHERE post_id = ?1", [post_id], |row| row.get(0)).unwrap();
+        assert_eq!(tags, 0);
+    }
+}
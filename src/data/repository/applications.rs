@@ -0,0 +1,156 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+pub struct NewApplication {
+    pub post_id: i64,
+    pub applicant_id: String,
+    pub employer_id: String,
+    pub answers: serde_json::Value,
+}
+
+// An application as the student who submitted it sees it.
+#[derive(Debug, Serialize)]
+pub struct SubmittedApplication {
+    pub id: i64,
+    pub post_id: i64,
+    pub post_title: String,
+    pub company_name: String,
+    pub status: String,
+    pub answers: serde_json::Value,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// An application as the employer who received it sees it.
+#[derive(Debug, Serialize)]
+pub struct ReceivedApplication {
+    pub id: i64,
+    pub post_id: i64,
+    pub post_title: String,
+    pub applicant: Applicant,
+    pub status: String,
+    pub answers: serde_json::Value,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Applicant {
+    pub id: String,
+    pub first_name: String,
+    pub last_name: String,
+    // Only when the applicant's privacy settings show it.
+    pub email: Option<String>,
+}
+
+pub struct ApplicationRepo<'a> {
+    conn: &'a Connection,
+}
+
+// Answers are stored as JSON text; anything unreadable is shown as an empty object.
+fn answers_column(row: &rusqlite::Row, index: usize) -> rusqlite::Result<serde_json::Value> {
+    let raw: String = row.get(index)?;
+    Ok(serde_json::from_str(&raw).unwrap_or(serde_json::json!({})))
+}
+
+impl<'a> ApplicationRepo<'a> {
+    pub fn new(conn: &'a Connection) -> Self {
+        ApplicationRepo { conn }
+    }
+
+    pub fn insert(&self, application: &NewApplication) -> rusqlite::Result<i64> {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT INTO applications (post_id, applicant_id, employer_id, status, answers, created_at, updated_at)
+             VALUES (?1, ?2, ?3, 'pending', ?4, ?5, ?5)",
+            params![
+                application.post_id,
+                application.applicant_id,
+                application.employer_id,
+                application.answers.to_string(),
+                now
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn list_submitted(&self, applicant_id: &str) -> rusqlite::Result<Vec<SubmittedApplication>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.id, a.post_id, p.title, p.company_name, a.status, a.answers, a.created_at, a.updated_at
+             FROM applications a
+             JOIN posts p ON a.post_id = p.id
+             WHERE a.applicant_id = ?1"
+        )?;
+        let applications = stmt.query_map([applicant_id], |row| {
+            Ok(SubmittedApplication {
+                id: row.get(0)?,
+                post_id: row.get(1)?,
+                post_title: row.get(2)?,
+                company_name: row.get(3)?,
+                status: row.get(4)?,
+                answers: answers_column(row, 5)?,
+                created_at: row.get(6)?,
+                updated_at: row.get(7)?,
+            })
+        })?;
+        applications.collect()
+    }
+
+    pub fn list_received(&self, employer_id: &str) -> rusqlite::Result<Vec<ReceivedApplication>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.id, a.post_id, p.title, a.applicant_id, u.first_name, u.last_name,
+                    CASE WHEN json_extract(u.profile, '$.preferences.privacy_settings.show_email') THEN u.email END,
+                    a.status, a.answers, a.created_at, a.updated_at
+             FROM applications a
+             JOIN posts p ON a.post_id = p.id
+             JOIN accounts u ON a.applicant_id = u.unique_id
+             WHERE a.employer_id = ?1"
+        )?;
+        let applications = stmt.query_map([employer_id], |row| {
+            Ok(ReceivedApplication {
+                id: row.get(0)?,
+                post_id: row.get(1)?,
+                post_title: row.get(2)?,
+                applicant: Applicant {
+                    id: row.get(3)?,
+                    first_name: row.get(4)?,
+                    last_name: row.get(5)?,
+                    email: row.get(6)?,
+                },
+                status: row.get(7)?,
+                answers: answers_column(row, 8)?,
+                created_at: row.get(9)?,
+                updated_at: row.get(10)?,
+            })
+        })?;
+        applications.collect()
+    }
+
+    pub fn count_for_post(&self, post_id: i64) -> rusqlite::Result<i64> {
+        self.conn.query_row("SELECT COUNT(*) FROM applications WHERE post_id = ?1", [post_id], |row| row.get(0))
+    }
+
+    pub fn has_applied(&self, post_id: i64, applicant_id: &str) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM applications WHERE post_id = ?1 AND applicant_id = ?2)",
+            params![post_id, applicant_id],
+            |row| row.get(0),
+        )
+    }
+
+    // The unique_id of the employer the application was sent to, if it exists.
+    pub fn employer_of(&self, id: i64) -> rusqlite::Result<Option<String>> {
+        self.conn.query_row(
+            "SELECT employer_id FROM applications WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        ).optional()
+    }
+
+    pub fn set_status(&self, id: i64, status: &str) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE applications SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            params![status, chrono::Utc::now().to_rfc3339(), id],
+        )
+    }
+}
@@ -0,0 +1,126 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Debug, Serialize)]
+pub struct Message {
+    pub id: i64,
+    pub content: String,
+    pub sender_id: i64,
+    pub receiver_id: i64,
+    pub timestamp: String,
+    pub read: bool,
+    pub message_type: Option<String>,
+    pub file_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct NewMessage {
+    #[validate(range(min = 1))]
+    pub receiver_id: i64,
+    #[validate(length(min = 1, max = 5000))]
+    pub content: String,
+    #[validate(length(max = 50))]
+    pub message_type: Option<String>,
+    #[validate(length(max = 2048))]
+    pub file_url: Option<String>,
+}
+
+// The other participant of a conversation and the latest message exchanged with them.
+#[derive(Debug, Serialize)]
+pub struct Conversation {
+    pub id: i64,
+    pub first_name: String,
+    pub last_name: String,
+    pub pfp: String,
+    pub last_message: Option<LastMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastMessage {
+    pub content: String,
+    pub timestamp: String,
+    pub unread: bool,
+}
+
+pub struct MessageRepo<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> MessageRepo<'a> {
+    pub fn new(conn: &'a Connection) -> Self {
+        MessageRepo { conn }
+    }
+
+    pub fn insert(&self, sender_id: i64, message: &NewMessage) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO messages (sender_id, receiver_id, content, timestamp, read, message_type, file_url)
+             VALUES (?1, ?2, ?3, datetime('now'), false, ?4, ?5)",
+            params![sender_id, message.receiver_id, message.content, message.message_type, message.file_url],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    // Both directions of the conversation between two accounts, oldest first.
+    pub fn list_between(&self, account_id: i64, other_id: i64) -> rusqlite::Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, content, sender_id, receiver_id, timestamp, read, message_type, file_url
+             FROM messages
+             WHERE (sender_id = ?1 AND receiver_id = ?2)
+                OR (sender_id = ?2 AND receiver_id = ?1)
+             ORDER BY timestamp ASC, id ASC"
+        )?;
+        let messages = stmt.query_map(params![account_id, other_id], |row| {
+            Ok(Message {
+                id: row.get(0)?,
+                content: row.get(1)?,
+                sender_id: row.get(2)?,
+                receiver_id: row.get(3)?,
+                timestamp: row.get(4)?,
+                read: row.get(5)?,
+                message_type: row.get(6)?,
+                file_url: row.get(7)?,
+            })
+        })?;
+        messages.collect()
+    }
+
+    // Marks everything `sender_id` has sent to `receiver_id` as read.
+    pub fn mark_read(&self, sender_id: i64, receiver_id: i64) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE messages SET read = true WHERE sender_id = ?1 AND receiver_id = ?2 AND read = false",
+            params![sender_id, receiver_id],
+        )
+    }
+
+    // One entry per account `account_id` has exchanged messages with, most recent first.
+    pub fn conversations(&self, account_id: i64) -> rusqlite::Result<Vec<Conversation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT a.id, a.first_name, a.last_name, COALESCE(json_extract(a.profile, '$.pfp'), ''),
+                    m.content, m.timestamp, m.read, m.sender_id = ?1
+             FROM messages m
+             JOIN accounts a ON a.id = CASE WHEN m.sender_id = ?1 THEN m.receiver_id ELSE m.sender_id END
+             WHERE m.id IN (
+                 SELECT MAX(id) FROM messages
+                 WHERE sender_id = ?1 OR receiver_id = ?1
+                 GROUP BY CASE WHEN sender_id = ?1 THEN receiver_id ELSE sender_id END
+             )
+             ORDER BY m.timestamp DESC, m.id DESC"
+        )?;
+        let conversations = stmt.query_map([account_id], |row| {
+            Ok(Conversation {
+                id: row.get(0)?,
+                first_name: row.get(1)?,
+                last_name: row.get(2)?,
+                pfp: row.get(3)?,
+                last_message: Some(LastMessage {
+                    content: row.get(4)?,
+                    timestamp: row.get(5)?,
+                    unread: !row.get::<_, bool>(6)? && !row.get::<_, bool>(7)?,
+                }),
+            })
+        })?;
+        conversations.collect()
+    }
+}
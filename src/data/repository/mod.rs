@@ -0,0 +1,30 @@
+// All SQL against the application tables lives behind these repositories. Each one
+// borrows a plain `rusqlite::Connection`, so the same code runs against a pooled
+// connection in handlers or `Connection::open_in_memory()` in tests.
+
+pub mod accounts;
+pub mod applications;
+pub mod messages;
+pub mod posts;
+
+pub use accounts::AccountRepo;
+pub use applications::{ApplicationRepo, NewApplication};
+pub use messages::{MessageRepo, NewMessage};
+pub use posts::{Cursor, NewPost, PostChanges, PostRepo, PostSearch, PostSort};
+
+// Columns holding JSON are decoded into typed values; a malformed blob surfaces as a
+// conversion error on that column instead of a panic.
+fn json_column<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, index: usize) -> rusqlite::Result<T> {
+    let raw: String = row.get(index)?;
+    serde_json::from_str(&raw).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+// A fresh in-memory database with every migration applied.
+#[cfg(test)]
+pub(crate) fn test_connection() -> rusqlite::Connection {
+    let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    crate::migrations::run_pending(&mut conn).unwrap();
+    conn
+}
@@ -0,0 +1,873 @@
+use data_encoding::BASE64URL_NOPAD;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Deserializer, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::questions::{self, question_list, Question};
+
+use super::json_column;
+
+#[derive(Debug, Serialize)]
+pub struct Post {
+    pub id: i64,
+    pub title: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub documents: Vec<String>,
+    pub tips: String,
+    pub skills: Vec<String>,
+    pub experience: String,
+    pub jobtype: String,
+    pub location: String,
+    pub date: String,
+    pub questions: Vec<Question>,
+    pub company_name: String,
+    pub employer_id: String,
+    pub status: String,
+}
+
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(default)]
+#[validate(schema(function = "new_post_form"))]
+pub struct NewPost {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+    #[validate(length(min = 1, max = 10000))]
+    pub description: String,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 20), custom(function = "short_labels"))]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 20), custom(function = "document_names"))]
+    pub documents: Vec<String>,
+    #[validate(length(max = 5000))]
+    pub tips: String,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 30), custom(function = "short_labels"))]
+    pub skills: Vec<String>,
+    #[validate(length(max = 2000))]
+    pub experience: String,
+    #[validate(length(min = 1, max = 100))]
+    pub jobtype: String,
+    #[validate(length(min = 1, max = 200))]
+    pub location: String,
+    // Compared as text by the `date_from` filter, so it has to be ISO formatted.
+    #[validate(custom(function = "iso_date"))]
+    pub date: String,
+    // The application form; see `questions::Question`.
+    #[serde(deserialize_with = "question_list")]
+    #[validate(nested)]
+    pub questions: Vec<Question>,
+    #[validate(length(min = 1, max = 200))]
+    pub company_name: String,
+}
+
+// Splits the free-form text older clients send ("rust, sql" or a JSON array in a string)
+// into entries.
+pub fn parse_list(raw: &str) -> Vec<String> {
+    let raw = raw.trim();
+    if raw.starts_with('[') {
+        if let Ok(entries) = serde_json::from_str::<Vec<String>>(raw) {
+            return entries.into_iter().map(|entry| entry.trim().to_string()).filter(|entry| !entry.is_empty()).collect();
+        }
+    }
+    raw.split([',', ';', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Tags and skills are compared case-insensitively, so they are stored lower-cased with single spaces.
+pub fn normalize_label(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Normalized, without blanks and repeats, in their original order.
+pub fn clean_labels(labels: &[String]) -> Vec<String> {
+    dedup(labels.iter().map(|label| normalize_label(label)).collect())
+}
+
+pub fn clean_documents(names: &[String]) -> Vec<String> {
+    dedup(names.iter().map(|name| name.trim().to_string()).collect())
+}
+
+fn dedup(mut entries: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    entries.retain(|entry| !entry.is_empty() && seen.insert(entry.clone()));
+    entries
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a list of strings or a comma-separated string")]
+enum ListInput {
+    List(Vec<String>),
+    Text(String),
+}
+
+// Lists are JSON arrays, but the comma-separated strings earlier clients sent are still accepted.
+fn label_list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(match ListInput::deserialize(deserializer)? {
+        ListInput::List(entries) => entries,
+        ListInput::Text(text) => parse_list(&text),
+    })
+}
+
+fn short_labels(labels: &[String]) -> Result<(), ValidationError> {
+    match labels.iter().all(|label| !label.trim().is_empty() && label.chars().count() <= 50) {
+        true => Ok(()),
+        false => Err(ValidationError::new("entries").with_message("entries must be between 1 and 50 characters".into())),
+    }
+}
+
+fn document_names(names: &[String]) -> Result<(), ValidationError> {
+    match names.iter().all(|name| !name.trim().is_empty() && name.chars().count() <= 200) {
+        true => Ok(()),
+        false => Err(ValidationError::new("entries").with_message("entries must be between 1 and 200 characters".into())),
+    }
+}
+
+// A tag or skill and the number of accepted posts that list it.
+#[derive(Debug, Serialize)]
+pub struct LabelCount {
+    pub name: String,
+    pub count: i64,
+}
+
+fn new_post_form(post: &NewPost) -> Result<(), ValidationError> {
+    questions::check_form(&post.questions)
+}
+
+fn post_changes_form(changes: &PostChanges) -> Result<(), ValidationError> {
+    questions::check_form(&changes.questions)
+}
+
+// The fields an employer may edit on an existing post.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(default)]
+#[validate(schema(function = "post_changes_form"))]
+pub struct PostChanges {
+    #[validate(length(min = 1, max = 200))]
+    pub title: String,
+    #[validate(length(min = 1, max = 10000))]
+    pub description: String,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 20), custom(function = "short_labels"))]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 20), custom(function = "document_names"))]
+    pub documents: Vec<String>,
+    #[validate(length(max = 5000))]
+    pub tips: String,
+    #[serde(deserialize_with = "label_list")]
+    #[validate(length(max = 30), custom(function = "short_labels"))]
+    pub skills: Vec<String>,
+    #[validate(length(max = 2000))]
+    pub experience: String,
+    #[validate(length(min = 1, max = 100))]
+    pub jobtype: String,
+    #[validate(length(min = 1, max = 200))]
+    pub location: String,
+    // Compared as text by the `date_from` filter, so it has to be ISO formatted.
+    #[validate(custom(function = "iso_date"))]
+    pub date: String,
+    // The application form; see `questions::Question`.
+    #[serde(deserialize_with = "question_list")]
+    #[validate(nested)]
+    pub questions: Vec<Question>,
+}
+
+// Query string of `GET /api/v1/posts`. List filters (`tags`, `skills`) are comma-separated
+// and every entry has to match.
+#[derive(Debug, Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct PostSearch {
+    #[validate(length(max = 200))]
+    pub q: Option<String>,
+    #[validate(length(max = 100))]
+    pub jobtype: Option<String>,
+    #[validate(length(max = 200))]
+    pub location: Option<String>,
+    #[validate(length(max = 500))]
+    pub tags: Option<String>,
+    #[validate(length(max = 500))]
+    pub skills: Option<String>,
+    #[validate(length(max = 200))]
+    pub company: Option<String>,
+    #[validate(custom(function = "iso_date"))]
+    pub date_from: Option<String>,
+    #[validate(custom(function = "known_sort"))]
+    pub sort: Option<String>,
+    #[validate(length(max = 500))]
+    pub cursor: Option<String>,
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<i64>,
+}
+
+fn iso_date(value: &str) -> Result<(), ValidationError> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|_| ())
+        .map_err(|_| ValidationError::new("date").with_message("must be a date like 2025-01-31".into()))
+}
+
+fn known_sort(value: &str) -> Result<(), ValidationError> {
+    match PostSort::parse(value) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("one_of").with_message("must be one of: newest, oldest, relevance".into())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    Newest,
+    Oldest,
+    Relevance,
+}
+
+impl PostSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "newest" => Some(PostSort::Newest),
+            "oldest" => Some(PostSort::Oldest),
+            "relevance" => Some(PostSort::Relevance),
+            _ => None,
+        }
+    }
+}
+
+// Where the previous page stopped: the sort key and id of its last post. Handed to clients
+// as an opaque base64 string.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    sort: PostSort,
+    key: CursorKey,
+    id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CursorKey {
+    Rank(f64),
+    Date(String),
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(serde_json::to_string(self).unwrap_or_default().as_bytes())
+    }
+
+    pub fn decode(value: &str) -> Option<Cursor> {
+        let raw = BASE64URL_NOPAD.decode(value.as_bytes()).ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    pub fn sort(&self) -> PostSort {
+        self.sort
+    }
+}
+
+// Search terms marked up with <mark>; the rest of the text is HTML-escaped.
+#[derive(Debug, Serialize)]
+pub struct Highlights {
+    pub title: String,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub post: Post,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlights: Option<Highlights>,
+    #[serde(skip)]
+    rank: f64,
+}
+
+impl SearchHit {
+    pub fn cursor(&self, sort: PostSort) -> Cursor {
+        let key = match sort {
+            PostSort::Relevance => CursorKey::Rank(self.rank),
+            PostSort::Newest | PostSort::Oldest => CursorKey::Date(self.post.date.clone()),
+        };
+        Cursor { sort, key, id: self.post.id }
+    }
+}
+
+pub struct SearchPage {
+    pub hits: Vec<SearchHit>,
+    pub total: i64,
+}
+
+impl PostSearch {
+    fn filter(value: &Option<String>) -> Option<&str> {
+        value.as_deref().map(str::trim).filter(|value| !value.is_empty())
+    }
+
+    // `q` as an FTS5 query: every word has to appear, the last one may be a prefix.
+    pub fn text_query(&self) -> Option<String> {
+        let terms: Vec<String> = Self::filter(&self.q)?
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(|term| format!("\"{}\"", term))
+            .collect();
+        match terms.is_empty() {
+            true => None,
+            false => Some(format!("{}*", terms.join(" "))),
+        }
+    }
+
+    pub fn sort(&self) -> PostSort {
+        match (self.sort.as_deref().and_then(PostSort::parse), self.text_query()) {
+            (Some(sort), _) => sort,
+            (None, Some(_)) => PostSort::Relevance,
+            (None, None) => PostSort::Newest,
+        }
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(20)
+    }
+
+    // Every tag and skill filter entry, in the form they are stored in.
+    fn labels(list: &Option<String>) -> Vec<String> {
+        Self::filter(list).map(parse_list).unwrap_or_default().iter().map(|label| normalize_label(label)).collect()
+    }
+}
+
+// `%` and `_` in user input match themselves in LIKE patterns.
+fn contains_pattern(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+// FTS5 wraps matches in these bytes. They are stripped from titles and descriptions before
+// they are stored, so the text around them can be escaped before they become tags.
+const MARK_START: char = '\u{1}';
+const MARK_END: char = '\u{2}';
+
+fn without_markers(text: &str) -> String {
+    text.replace([MARK_START, MARK_END], "")
+}
+
+// Rows stored before markers were stripped may still hold one, so tags are only emitted in
+// matching pairs.
+fn mark_up(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut open = false;
+    for c in text.chars() {
+        match c {
+            MARK_START if !open => {
+                out.push_str("<mark>");
+                open = true;
+            }
+            MARK_END if open => {
+                out.push_str("</mark>");
+                open = false;
+            }
+            MARK_START | MARK_END => {}
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    if open {
+        out.push_str("</mark>");
+    }
+    out
+}
+
+fn questions_json(questions: &[Question]) -> rusqlite::Result<String> {
+    serde_json::to_string(questions).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+pub struct PostRepo<'a> {
+    conn: &'a Connection,
+}
+
+// Tags, documents and skills live in their own tables and come back as JSON arrays.
+const POST_COLUMNS: &str = "posts.id, posts.title, posts.description, \
+    (SELECT json_group_array(tag) FROM post_tags WHERE post_id = posts.id), \
+    (SELECT json_group_array(name) FROM (SELECT name FROM post_required_documents WHERE post_id = posts.id ORDER BY position)), \
+    posts.tips, \
+    (SELECT json_group_array(skill) FROM post_skills WHERE post_id = posts.id), \
+    posts.experience, posts.jobtype, posts.location, posts.date, posts.questions, posts.company_name, posts.employer_id, posts.status";
+
+fn post_from_row(row: &rusqlite::Row) -> rusqlite::Result<Post> {
+    Ok(Post {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        description: row.get(2)?,
+        tags: json_column(row, 3)?,
+        documents: json_column(row, 4)?,
+        tips: row.get(5)?,
+        skills: json_column(row, 6)?,
+        experience: row.get(7)?,
+        jobtype: row.get(8)?,
+        location: row.get(9)?,
+        date: row.get(10)?,
+        questions: json_column(row, 11)?,
+        company_name: row.get(12)?,
+        employer_id: row.get(13)?,
+        status: row.get(14)?,
+    })
+}
+
+impl<'a> PostRepo<'a> {
+    pub fn new(conn: &'a Connection) -> Self {
+        PostRepo { conn }
+    }
+
+    // New posts wait in the moderation queue until an administrator accepts them.
+    pub fn insert(&self, employer_id: &str, post: &NewPost) -> rusqlite::Result<i64> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO posts (title, description, tags, documents, tips, skills, experience, jobtype, location, date, questions, company_name, employer_id, status)
+             VALUES (?1, ?2, '', '', ?3, '', ?4, ?5, ?6, ?7, ?8, ?9, ?10, 'Pending')",
+            params![
+                without_markers(&post.title),
+                without_markers(&post.description),
+                post.tips,
+                post.experience,
+                post.jobtype,
+                post.location,
+                post.date,
+                questions_json(&post.questions)?,
+                post.company_name,
+                employer_id
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        PostRepo::new(&tx).set_lists(id, &post.tags, &post.skills, &post.documents)?;
+        tx.commit()?;
+        Ok(id)
+    }
+
+    // Replaces the post's tags, skills and required documents. The text columns on `posts`
+    // keep a comma-separated copy for the full-text index.
+    fn set_lists(&self, id: i64, tags: &[String], skills: &[String], documents: &[String]) -> rusqlite::Result<()> {
+        let (tags, skills, documents) = (clean_labels(tags), clean_labels(skills), clean_documents(documents));
+
+        for table in ["post_tags", "post_skills", "post_required_documents"] {
+            self.conn.execute(&format!("DELETE FROM {} WHERE post_id = ?1", table), [id])?;
+        }
+        for tag in &tags {
+            self.conn.execute("INSERT INTO post_tags (post_id, tag) VALUES (?1, ?2)", params![id, tag])?;
+        }
+        for skill in &skills {
+            self.conn.execute("INSERT INTO post_skills (post_id, skill) VALUES (?1, ?2)", params![id, skill])?;
+        }
+        for (position, name) in documents.iter().enumerate() {
+            self.conn.execute(
+                "INSERT INTO post_required_documents (post_id, position, name) VALUES (?1, ?2, ?3)",
+                params![id, position as i64, name],
+            )?;
+        }
+        self.conn.execute(
+            "UPDATE posts SET tags = ?1, skills = ?2, documents = ?3 WHERE id = ?4",
+            params![tags.join(", "), skills.join(", "), documents.join(", "), id],
+        )?;
+        Ok(())
+    }
+
+    pub fn find_by_id(&self, id: i64) -> rusqlite::Result<Option<Post>> {
+        self.conn.query_row(
+            &format!("SELECT {} FROM posts WHERE posts.id = ?1", POST_COLUMNS),
+            [id],
+            post_from_row,
+        ).optional()
+    }
+
+    // Counts the viewer once per post; returns whether this was their first view.
+    pub fn record_view(&self, id: i64, viewer: &str) -> rusqlite::Result<bool> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO post_views (post_id, viewer, viewed_at) VALUES (?1, ?2, ?3)",
+            params![id, viewer, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(inserted > 0)
+    }
+
+    pub fn view_count(&self, id: i64) -> rusqlite::Result<i64> {
+        self.conn.query_row("SELECT COUNT(*) FROM post_views WHERE post_id = ?1", [id], |row| row.get(0))
+    }
+
+    pub fn list_by_status(&self, status: &str) -> rusqlite::Result<Vec<Post>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM posts WHERE status = ?1 ORDER BY date DESC", POST_COLUMNS)
+        )?;
+        let posts = stmt.query_map([status], post_from_row)?;
+        posts.collect()
+    }
+
+    // Accepted posts matching `search`, one page at a time, plus how many match in total.
+    pub fn search(&self, search: &PostSearch, after: Option<&Cursor>) -> rusqlite::Result<SearchPage> {
+        let sort = search.sort();
+        let text_query = search.text_query();
+
+        let mut from = String::from("FROM posts");
+        let mut conditions = vec!["posts.status = 'Accepted'".to_string()];
+        let mut args: Vec<Value> = Vec::new();
+        if let Some(text_query) = &text_query {
+            from.push_str(" JOIN posts_fts ON posts_fts.rowid = posts.id");
+            conditions.push("posts_fts MATCH ?".to_string());
+            args.push(Value::Text(text_query.clone()));
+        }
+        for (table, column, list) in [("post_tags", "tag", &search.tags), ("post_skills", "skill", &search.skills)] {
+            for label in PostSearch::labels(list) {
+                conditions.push(format!("EXISTS (SELECT 1 FROM {} WHERE post_id = posts.id AND {} = ?)", table, column));
+                args.push(Value::Text(label));
+            }
+        }
+        if let Some(jobtype) = PostSearch::filter(&search.jobtype) {
+            conditions.push("posts.jobtype = ? COLLATE NOCASE".to_string());
+            args.push(Value::Text(jobtype.to_string()));
+        }
+        for (column, value) in [("posts.location", &search.location), ("posts.company_name", &search.company)] {
+            if let Some(value) = PostSearch::filter(value) {
+                conditions.push(format!("{} LIKE ? ESCAPE '\\'", column));
+                args.push(Value::Text(contains_pattern(value)));
+            }
+        }
+        if let Some(date_from) = PostSearch::filter(&search.date_from) {
+            conditions.push("posts.date >= ?".to_string());
+            args.push(Value::Text(date_from.to_string()));
+        }
+
+        let total = self.conn.query_row(
+            &format!("SELECT COUNT(*) {} WHERE {}", from, conditions.join(" AND ")),
+            params_from_iter(args.iter()),
+            |row| row.get(0),
+        )?;
+
+        let rank = match text_query.is_some() {
+            true => "bm25(posts_fts, 10.0, 1.0, 5.0, 5.0)",
+            false => "0.0",
+        };
+        let highlights = match text_query.is_some() {
+            true => "highlight(posts_fts, 0, char(1), char(2)), snippet(posts_fts, 1, char(1), char(2), '…', 24)",
+            false => "NULL, NULL",
+        };
+        let order = match sort {
+            PostSort::Newest => "posts.date DESC, posts.id DESC".to_string(),
+            PostSort::Oldest => "posts.date ASC, posts.id ASC".to_string(),
+            PostSort::Relevance => format!("{} ASC, posts.id ASC", rank),
+        };
+        match after.map(|cursor| &cursor.key) {
+            Some(CursorKey::Date(date)) => {
+                let op = if sort == PostSort::Oldest { ">" } else { "<" };
+                conditions.push(format!("(posts.date {op} ? OR (posts.date = ? AND posts.id {op} ?))"));
+                args.extend([Value::Text(date.clone()), Value::Text(date.clone())]);
+            },
+            Some(CursorKey::Rank(value)) => {
+                conditions.push(format!("({rank} > ? OR ({rank} = ? AND posts.id > ?))"));
+                args.extend([Value::Real(*value), Value::Real(*value)]);
+            },
+            None => {}
+        }
+        if let Some(cursor) = after {
+            args.push(Value::Integer(cursor.id));
+        }
+        // One extra row tells the caller whether there is a next page.
+        args.push(Value::Integer(search.limit() + 1));
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT {}, {}, {} {} WHERE {} ORDER BY {} LIMIT ?",
+            POST_COLUMNS, rank, highlights, from, conditions.join(" AND "), order
+        ))?;
+        let hits = stmt.query_map(params_from_iter(args.iter()), |row| {
+            let title: Option<String> = row.get(16)?;
+            let description: Option<String> = row.get(17)?;
+            Ok(SearchHit {
+                post: post_from_row(row)?,
+                rank: row.get(15)?,
+                highlights: title.zip(description).map(|(title, description)| Highlights {
+                    title: mark_up(&title),
+                    description: mark_up(&description),
+                }),
+            })
+        })?.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(SearchPage { hits, total })
+    }
+
+    pub fn list_by_employer(&self, employer_id: &str) -> rusqlite::Result<Vec<Post>> {
+        let mut stmt = self.conn.prepare(
+            &format!("SELECT {} FROM posts WHERE employer_id = ?1", POST_COLUMNS)
+        )?;
+        let posts = stmt.query_map([employer_id], post_from_row)?;
+        posts.collect()
+    }
+
+    pub fn count_by_employer(&self, employer_id: &str) -> rusqlite::Result<i64> {
+        self.conn.query_row("SELECT COUNT(*) FROM posts WHERE employer_id = ?1", [employer_id], |row| row.get(0))
+    }
+
+    // The unique_id of the employer who owns the post, if it exists.
+    pub fn employer_of(&self, id: i64) -> rusqlite::Result<Option<String>> {
+        self.conn.query_row(
+            "SELECT employer_id FROM posts WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        ).optional()
+    }
+
+    pub fn set_status(&self, id: i64, status: &str) -> rusqlite::Result<usize> {
+        self.conn.execute("UPDATE posts SET status = ?1 WHERE id = ?2", params![status, id])
+    }
+
+    pub fn update(&self, id: i64, changes: &PostChanges) -> rusqlite::Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE posts SET
+                title = ?1,
+                description = ?2,
+                tips = ?3,
+                experience = ?4,
+                jobtype = ?5,
+                location = ?6,
+                date = ?7,
+                questions = ?8
+            WHERE id = ?9",
+            params![
+                without_markers(&changes.title),
+                without_markers(&changes.description),
+                changes.tips,
+                changes.experience,
+                changes.jobtype,
+                changes.location,
+                changes.date,
+                questions_json(&changes.questions)?,
+                id
+            ],
+        )?;
+        if updated > 0 {
+            PostRepo::new(&tx).set_lists(id, &changes.tags, &changes.skills, &changes.documents)?;
+        }
+        tx.commit()?;
+        Ok(updated)
+    }
+
+    // The most used tags across accepted posts.
+    pub fn popular_tags(&self, limit: i64) -> rusqlite::Result<Vec<LabelCount>> {
+        self.popular("post_tags", "tag", limit)
+    }
+
+    pub fn popular_skills(&self, limit: i64) -> rusqlite::Result<Vec<LabelCount>> {
+        self.popular("post_skills", "skill", limit)
+    }
+
+    fn popular(&self, table: &str, column: &str, limit: i64) -> rusqlite::Result<Vec<LabelCount>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT l.{column}, COUNT(*) AS uses FROM {table} l
+             JOIN posts ON posts.id = l.post_id AND posts.status = 'Accepted'
+             GROUP BY l.{column} ORDER BY uses DESC, l.{column} ASC LIMIT ?1"
+        ))?;
+        let labels = stmt.query_map([limit], |row| Ok(LabelCount { name: row.get(0)?, count: row.get(1)? }))?;
+        labels.collect()
+    }
+
+    // Removes the post together with the applications submitted to it.
+    pub fn delete(&self, id: i64) -> rusqlite::Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM applications WHERE post_id = ?1", [id])?;
+        for table in ["post_tags", "post_skills", "post_required_documents", "post_views"] {
+            tx.execute(&format!("DELETE FROM {} WHERE post_id = ?1", table), [id])?;
+        }
+        let deleted = tx.execute("DELETE FROM posts WHERE id = ?1", [id])?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::accounts::tests::insert_account;
+    use crate::users::AccountType;
+
+    fn employer(conn: &Connection) -> String {
+        insert_account(conn, "employer@example.com", AccountType::Employer).unique_id
+    }
+
+    #[test]
+    fn insert_cleans_lists() {
+        let conn = crate::repository::test_connection();
+        let repo = PostRepo::new(&conn);
+        let post = NewPost {
+            title: "Intern".to_string(),
+            date: "2026-01-01".to_string(),
+            tags: vec!["  Remote  Work ".to_string(), "remote work".to_string(), "".to_string()],
+            skills: parse_list("Rust; SQL"),
+            documents: vec![" Resume ".to_string(), "Resume".to_string(), "Cover letter".to_string()],
+            ..Default::default()
+        };
+        let employer = employer(&conn);
+        let id = repo.insert(&employer, &post).unwrap();
+
+        let stored = repo.find_by_id(id).unwrap().unwrap();
+        assert_eq!(stored.tags, vec!["remote work"]);
+        assert_eq!(stored.skills, vec!["rust", "sql"]);
+        assert_eq!(stored.documents, vec!["Resume", "Cover letter"]);
+        assert_eq!(stored.status, "Pending");
+        assert_eq!(repo.employer_of(id).unwrap(), Some(employer));
+    }
+
+    #[test]
+    fn views_count_once_per_viewer() {
+        let conn = crate::repository::test_connection();
+        let repo = PostRepo::new(&conn);
+        let id = repo.insert(&employer(&conn), &NewPost { title: "Intern".to_string(), ..Default::default() }).unwrap();
+
+        assert!(repo.record_view(id, "account:a").unwrap());
+        assert!(!repo.record_view(id, "account:a").unwrap());
+        assert!(repo.record_view(id, "account:b").unwrap());
+        assert_eq!(repo.view_count(id).unwrap(), 2);
+
+        repo.delete(id).unwrap();
+        assert!(repo.find_by_id(id).unwrap().is_none());
+        assert_eq!(repo.view_count(id).unwrap(), 0);
+    }
+
+    #[test]
+    fn parse_list_accepts_text_and_json() {
+        assert_eq!(parse_list("rust, sql;\ngo"), vec!["rust", "sql", "go"]);
+        assert_eq!(parse_list(r#"[" rust ", "", "a, b"]"#), vec!["rust", "a, b"]);
+        assert!(parse_list("  ").is_empty());
+    }
+
+    fn accepted(repo: &PostRepo, employer: &str, post: NewPost) -> i64 {
+        let id = repo.insert(employer, &post).unwrap();
+        repo.set_status(id, "Accepted").unwrap();
+        id
+    }
+
+    fn search(repo: &PostRepo, query: &str) -> Vec<i64> {
+        let search: PostSearch = serde_json::from_value(serde_json::from_str(query).unwrap()).unwrap();
+        repo.search(&search, None).unwrap().hits.iter().map(|hit| hit.post.id).collect()
+    }
+
+    #[test]
+    fn search_filters() {
+        let conn = crate::repository::test_connection();
+        let repo = PostRepo::new(&conn);
+        let employer = employer(&conn);
+        let rust = accepted(&repo, &employer, NewPost {
+            title: "Rust developer".to_string(),
+            description: "Backend services".to_string(),
+            tags: vec!["Remote".to_string(), "Internship".to_string()],
+            skills: vec!["Rust".to_string()],
+            jobtype: "Part-time".to_string(),
+            location: "Boston, MA".to_string(),
+            company_name: "100% Oxide".to_string(),
+            date: "2026-03-01".to_string(),
+            ..Default::default()
+        });
+        let barista = accepted(&repo, &employer, NewPost {
+            title: "Barista".to_string(),
+            description: "Coffee and developer chats".to_string(),
+            tags: vec!["Remote".to_string()],
+            jobtype: "Full-time".to_string(),
+            location: "Denver".to_string(),
+            company_name: "Beans".to_string(),
+            date: "2026-01-15".to_string(),
+            ..Default::default()
+        });
+        repo.insert(&employer, &NewPost { title: "Rust pending".to_string(), date: "2026-05-01".to_string(), ..Default::default() }).unwrap();
+
+        assert_eq!(search(&repo, "{}"), vec![rust, barista]);
+        assert_eq!(search(&repo, r#"{"sort": "oldest"}"#), vec![barista, rust]);
+        assert_eq!(search(&repo, r#"{"q": "rust"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"q": "develop"}"#), vec![rust, barista]);
+        assert_eq!(search(&repo, r#"{"tags": "remote, internship"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"skills": "RUST"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"jobtype": "full-time"}"#), vec![barista]);
+        assert_eq!(search(&repo, r#"{"location": "boston"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"company": "100%"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"company": "%"}"#), vec![rust]);
+        assert_eq!(search(&repo, r#"{"date_from": "2026-02-01"}"#), vec![rust]);
+        assert!(search(&repo, r#"{"q": "rust", "location": "denver"}"#).is_empty());
+    }
+
+    #[test]
+    fn highlights_escape_text_and_ignore_stray_markers() {
+        let conn = crate::repository::test_connection();
+        let repo = PostRepo::new(&conn);
+        let employer = employer(&conn);
+        let id = accepted(&repo, &employer, NewPost {
+            title: "Rust <dev>\u{1}".to_string(),
+            description: "\u{2}Rust & friends".to_string(),
+            date: "2026-03-01".to_string(),
+            ..Default::default()
+        });
+
+        let post = repo.find_by_id(id).unwrap().unwrap();
+        assert_eq!(post.title, "Rust <dev>");
+        let search: PostSearch = serde_json::from_str(r#"{"q": "rust"}"#).unwrap();
+        let highlights = repo.search(&search, None).unwrap().hits.remove(0).highlights.unwrap();
+        assert_eq!(highlights.title, "<mark>Rust</mark> &lt;dev&gt;");
+        assert_eq!(highlights.description, "<mark>Rust</mark> &amp; friends");
+
+        assert_eq!(mark_up("a\u{2}b\u{1}c\u{1}d"), "ab<mark>cd</mark>");
+    }
+
+    #[test]
+    fn cursor_pages_through_results() {
+        let conn = crate::repository::test_connection();
+        let repo = PostRepo::new(&conn);
+        let employer = employer(&conn);
+        let ids: Vec<i64> = ["2026-01-01", "2026-01-02", "2026-01-02"].iter()
+            .map(|date| accepted(&repo, &employer, NewPost { title: "Intern".to_string(), date: date.to_string(), ..Default::default() }))
+            .collect();
+
+        let search = PostSearch { limit: Some(1), ..Default::default() };
+        let mut seen = Vec::new();
+        let mut after: Option<Cursor> = None;
+        loop {
+            let page = repo.search(&search, after.as_ref()).unwrap();
+            assert_eq!(page.total, 3);
+            seen.push(page.hits[0].post.id);
+            if page.hits.len() == 1 {
+                break;
+            }
+            let cursor = page.hits[0].cursor(search.sort()).encode();
+            after = Some(Cursor::decode(&cursor).unwrap());
+        }
+        assert_eq!(seen, vec![ids[2], ids[1], ids[0]]);
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor { sort: PostSort::Relevance, key: CursorKey::Rank(-1.5), id: 7 };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.sort(), PostSort::Relevance);
+        assert!(matches!(decoded.key, CursorKey::Rank(rank) if rank == -1.5));
+        assert_eq!(decoded.id, 7);
+
+        assert!(Cursor::decode("not a cursor").is_none());
+        assert!(Cursor::decode(&BASE64URL_NOPAD.encode(b"{}")).is_none());
+    }
+
+    #[test]
+    fn text_query_and_sort() {
+        let search = |q: &str| PostSearch { q: Some(q.to_string()), ..Default::default() };
+        assert_eq!(search("rust dev").text_query().as_deref(), Some(r#""rust" "dev"*"#));
+        assert_eq!(search(r#"c++ "OR" -x"#).text_query().as_deref(), Some(r#""c" "OR" "x"*"#));
+        assert!(search(" ?! ").text_query().is_none());
+        assert_eq!(search("rust").sort(), PostSort::Relevance);
+        assert_eq!(PostSearch::default().sort(), PostSort::Newest);
+        assert_eq!(PostSearch { sort: Some("oldest".to_string()), ..search("rust") }.sort(), PostSort::Oldest);
+    }
+
+    #[test]
+    fn dates_must_be_iso() {
+        let post = |date: &str| NewPost { title: "Intern".to_string(), description: "d".to_string(), jobtype: "j".to_string(), location: "l".to_string(), company_name: "c".to_string(), date: date.to_string(), ..Default::default() };
+        assert!(post("2026-02-28").validate().is_ok());
+        assert!(post("next week").validate().is_err());
+        assert!(post("2026-02-30").validate().is_err());
+        assert!(PostSearch { date_from: Some("01/02/2026".to_string()), ..Default::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt;
+
+use rusqlite::{params, OptionalExtension};
+use serde::Serialize;
+
+use crate::tokens;
+
+pub const REFRESH_TOKEN_TTL_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+// Where a token was handed out to, kept so users can recognise their sessions.
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+// A signed-in device: one refresh token family, described by its live token.
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: String,
+    pub created_at: i64,
+    pub last_used_at: i64,
+    pub expires_at: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// A refresh token and the session (token family) it belongs to. Access tokens carry the
+// session id, so revoking the session cuts them off as well.
+pub struct IssuedSession {
+    pub id: String,
+    pub refresh_token: String,
+}
+
+pub struct RotatedSession {
+    pub account_id: String,
+    pub session: IssuedSession,
+}
+
+struct StoredToken {
+    id: i64,
+    family_id: String,
+    account_id: String,
+    expires_at: i64,
+    rotated_at: Option<i64>,
+    revoked_at: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum RefreshError {
+    Invalid,
+    Expired,
+    Reused,
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefreshError::Invalid => write!(f, "Invalid refresh token"),
+            RefreshError::Expired => write!(f, "Refresh token has expired"),
+            RefreshError::Reused => write!(f, "Refresh token has already been used or revoked"),
+            RefreshError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for RefreshError {
+    fn from(e: rusqlite::Error) -> Self {
+        RefreshError::Database(e)
+    }
+}
+
+fn insert_token(conn: &rusqlite::Connection, account_id: &str, family_id: &str, client: &ClientInfo) -> rusqlite::Result<String> {
+    let token = tokens::generate_opaque_token();
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "INSERT INTO refresh_tokens (token_hash, family_id, account_id, created_at, expires_at, ip, user_agent)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            tokens::hash_opaque_token(&token),
+            family_id,
+            account_id,
+            now,
+            now + REFRESH_TOKEN_TTL_SECONDS,
+            client.ip,
+            client.user_agent
+        ],
+    )?;
+    Ok(token)
+}
+
+// Starts a new token family, one per login.
+pub fn issue(conn: &rusqlite::Connection, account_id: &str, client: &ClientInfo) -> rusqlite::Result<IssuedSession> {
+    let family_id = uuid::Uuid::new_v4().to_string();
+    let refresh_token = insert_token(conn, account_id, &family_id, client)?;
+    Ok(IssuedSession { id: family_id, refresh_token })
+}
+
+// Exchanges a refresh token for its successor. Presenting a token that was already
+// rotated or revoked means it leaked, so the whole family is revoked.
+pub fn rotate(conn: &mut rusqlite::Connection, token: &str, client: &ClientInfo) -> Result<RotatedSession, RefreshError> {
+    let tx = conn.transaction()?;
+    let now = chrono::Utc::now().timestamp();
+
+    let stored = tx.query_row(
+        "SELECT id, family_id, account_id, expires_at, rotated_at, revoked_at
+         FROM refresh_tokens WHERE token_hash = ?1",
+        [tokens::hash_opaque_token(token)],
+        |row| Ok(StoredToken {
+            id: row.get(0)?,
+            family_id: row.get(1)?,
+            account_id: row.get(2)?,
+            expires_at: row.get(3)?,
+            rotated_at: row.get(4)?,
+            revoked_at: row.get(5)?,
+        }),
+    ).optional()?;
+
+    let StoredToken { id, family_id, account_id, expires_at, rotated_at, revoked_at } = match stored {
+        Some(stored) => stored,
+        None => return Err(RefreshError::Invalid),
+    };
+
+    if rotated_at.is_some() || revoked_at.is_some() {
+        println!("[WARN] Refresh token reuse detected, revoking session family {}", family_id);
+        tx.execute(
+            "UPDATE refresh_tokens SET revoked_at = ?1 WHERE family_id = ?2 AND revoked_at IS NULL",
+            params![now, family_id],
+        )?;
+        tx.commit()?;
+        return Err(RefreshError::Reused);
+    }
+
+    if expires_at <= now {
+        return Err(RefreshError::Expired);
+    }
+
+    tx.execute("UPDATE refresh_tokens SET rotated_at = ?1 WHERE id = ?2", params![now, id])?;
+    let refresh_token = insert_token(&tx, &account_id, &family_id, client)?;
+    tx.commit()?;
+
+    Ok(RotatedSession { account_id, session: IssuedSession { id: family_id, refresh_token } })
+}
+
+// Whether an access token issued for this session is still good: the session belongs to the
+// account and hasn't been signed out, revoked for reuse or cut off by `revoke_all`.
+pub fn is_active(conn: &rusqlite::Connection, account_id: &str, session_id: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (
+            SELECT 1 FROM refresh_tokens
+            WHERE family_id = ?1 AND account_id = ?2 AND revoked_at IS NULL AND expires_at > ?3
+        )",
+        params![session_id, account_id, chrono::Utc::now().timestamp()],
+        |row| row.get(0),
+    )
+}
+
+// Revokes every token in the family the given token belongs to.
+pub fn revoke(conn: &rusqlite::Connection, token: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE refresh_tokens SET revoked_at = ?1
+         WHERE revoked_at IS NULL
+         AND family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = ?2)",
+        params![chrono::Utc::now().timestamp(), tokens::hash_opaque_token(token)],
+    )?;
+    Ok(())
+}
+
+// Signs the account out everywhere, e.g. after its password changes.
+pub fn revoke_all(conn: &rusqlite::Connection, account_id: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE refresh_tokens SET revoked_at = ?1 WHERE account_id = ?2 AND revoked_at IS NULL",
+        params![chrono::Utc::now().timestamp(), account_id],
+    )
+}
+
+// The account's sessions that can still be refreshed, most recently used first.
+pub fn list(conn: &rusqlite::Connection, account_id: &str) -> rusqlite::Result<Vec<Session>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.family_id,
+                (SELECT MIN(f.created_at) FROM refresh_tokens f WHERE f.family_id = t.family_id),
+                t.created_at, t.expires_at, t.ip, t.user_agent
+         FROM refresh_tokens t
+         WHERE t.account_id = ?1 AND t.rotated_at IS NULL AND t.revoked_at IS NULL AND t.expires_at > ?2
+         ORDER BY t.created_at DESC, t.id DESC"
+    )?;
+    let sessions = stmt.query_map(params![account_id, chrono::Utc::now().timestamp()], |row| {
+        Ok(Session {
+            id: row.get(0)?,
+            created_at: row.get(1)?,
+            last_used_at: row.get(2)?,
+            expires_at: row.get(3)?,
+            ip: row.get(4)?,
+            user_agent: row.get(5)?,
+        })
+    })?;
+    sessions.collect()
+}
+
+// Signs one of the account's sessions out. Returns how many tokens were revoked, so 0 means
+// there was no such live session.
+pub fn revoke_session(conn: &rusqlite::Connection, account_id: &str, session_id: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE refresh_tokens SET revoked_at = ?1
+         WHERE family_id = ?2 AND account_id = ?3 AND revoked_at IS NULL",
+        params![chrono::Utc::now().timestamp(), session_id, account_id],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::accounts::tests::insert_account;
+    use crate::users::AccountType;
+
+    fn client() -> ClientInfo {
+        ClientInfo { ip: "203.0.113.7".to_string(), user_agent: Some("tests".to_string()) }
+    }
+
+    fn signed_in(conn: &rusqlite::Connection, email: &str) -> (String, IssuedSession) {
+        let account_id = insert_account(conn, email, AccountType::Student).unique_id;
+        let session = issue(conn, &account_id, &client()).unwrap();
+        (account_id, session)
+    }
+
+    #[test]
+    fn rotation_hands_out_a_new_token_in_the_same_session() {
+        let mut conn = crate::repository::test_connection();
+        let (account_id, issued) = signed_in(&conn, "ada@example.com");
+
+        let rotated = rotate(&mut conn, &issued.refresh_token, &client()).unwrap();
+        assert_eq!(rotated.account_id, account_id);
+        assert_eq!(rotated.session.id, issued.id);
+        assert_ne!(rotated.session.refresh_token, issued.refresh_token);
+        assert!(is_active(&conn, &account_id, &issued.id).unwrap());
+
+        let next = rotate(&mut conn, &rotated.session.refresh_token, &client()).unwrap();
+        assert_eq!(next.session.id, issued.id);
+        assert_eq!(list(&conn, &account_id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unknown_token_is_invalid() {
+        let mut conn = crate::repository::test_connection();
+        signed_in(&conn, "ada@example.com");
+
+        assert!(matches!(rotate(&mut conn, "not-a-token", &client()), Err(RefreshError::Invalid)));
+    }
+
+    #[test]
+    fn expired_token_cannot_be_rotated() {
+        let mut conn = crate::repository::test_connection();
+        let (_, issued) = signed_in(&conn, "ada@example.com");
+        conn.execute("UPDATE refresh_tokens SET expires_at = ?1", [chrono::Utc::now().timestamp() - 1]).unwrap();
+
+        assert!(matches!(rotate(&mut conn, &issued.refresh_token, &client()), Err(RefreshError::Expired)));
+    }
+
+    #[test]
+    fn reusing_a_rotated_token_revokes_the_whole_family() {
+        let mut conn = crate::repository::test_connection();
+        let (account_id, issued) = signed_in(&conn, "ada@example.com");
+        let other = issue(&conn, &account_id, &client()).unwrap();
+        let rotated = rotate(&mut conn, &issued.refresh_token, &client()).unwrap();
+
+        assert!(matches!(rotate(&mut conn, &issued.refresh_token, &client()), Err(RefreshError::Reused)));
+        assert!(!is_active(&conn, &account_id, &issued.id).unwrap());
+        assert!(matches!(rotate(&mut conn, &rotated.session.refresh_token, &client()), Err(RefreshError::Reused)));
+
+        // Other sessions of the same account are left alone.
+        assert!(is_active(&conn, &account_id, &other.id).unwrap());
+        assert!(rotate(&mut conn, &other.refresh_token, &client()).is_ok());
+    }
+
+    #[test]
+    fn revoke_all_signs_out_only_that_account() {
+        let mut conn = crate::repository::test_connection();
+        let (account_id, issued) = signed_in(&conn, "ada@example.com");
+        let (other_id, other) = signed_in(&conn, "grace@example.com");
+
+        assert_eq!(revoke_all(&conn, &account_id).unwrap(), 1);
+        assert!(!is_active(&conn, &account_id, &issued.id).unwrap());
+        assert!(matches!(rotate(&mut conn, &issued.refresh_token, &client()), Err(RefreshError::Reused)));
+        assert!(is_active(&conn, &other_id, &other.id).unwrap());
+    }
+}
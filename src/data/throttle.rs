@@ -0,0 +1,357 @@
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+
+// Failed logins are counted per email address and per client IP. Once a key passes its
+// allowance every further failure locks it for twice as long as the last, up to an hour.
+// An email's failures are forgotten a day after the last one. An IP's fade one at a time, so
+// a shared network (a whole school behind one address) making the odd typo all day never
+// builds up to a lockout.
+//
+// Each attempt is counted before the password (or two-factor code) is checked, and the one
+// that uses up the allowance holds the key until its check is done. A failed check keeps the
+// lock and logs it; a successful login clears the email's counter and gives the IP back its
+// attempt (but not its earlier failures, as it may be guessing at many accounts).
+const EMAIL_ALLOWANCE: i64 = 5;
+const IP_ALLOWANCE: i64 = 20;
+const BASE_LOCKOUT_SECONDS: i64 = 30;
+const MAX_LOCKOUT_SECONDS: i64 = 60 * 60;
+const EMAIL_FORGET_AFTER_SECONDS: i64 = 24 * 60 * 60;
+const IP_FORGIVE_EVERY_SECONDS: i64 = 3 * 60;
+
+const EMAIL_SCOPE: &str = "email";
+const IP_SCOPE: &str = "ip";
+
+#[derive(Debug, Serialize)]
+pub struct LockoutEvent {
+    pub id: i64,
+    pub scope: String,
+    pub key: String,
+    pub failures: i64,
+    pub locked_until: i64,
+    pub created_at: i64,
+}
+
+fn email_key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+// The scope, key and allowance of both counters an attempt is made against.
+fn counters(email: &str, ip: &str) -> [(&'static str, String, i64); 2] {
+    [
+        (EMAIL_SCOPE, email_key(email), EMAIL_ALLOWANCE),
+        (IP_SCOPE, ip.to_string(), IP_ALLOWANCE),
+    ]
+}
+
+fn lockout_seconds(failures: i64, allowance: i64) -> Option<i64> {
+    if failures < allowance {
+        return None;
+    }
+    let doublings = (failures - allowance).min(16) as u32;
+    Some((BASE_LOCKOUT_SECONDS << doublings).min(MAX_LOCKOUT_SECONDS))
+}
+
+// Seconds until the caller may try again, if either the email or the IP is locked out.
+pub fn retry_after(conn: &Connection, email: &str, ip: &str) -> rusqlite::Result<Option<i64>> {
+    let now = chrono::Utc::now().timestamp();
+    let locked_until: Option<i64> = conn.query_row(
+        "SELECT MAX(locked_until) FROM login_failures
+         WHERE (scope = ?1 AND key = ?2) OR (scope = ?3 AND key = ?4)",
+        params![EMAIL_SCOPE, email_key(email), IP_SCOPE, ip],
+        |row| row.get(0),
+    )?;
+    Ok(locked_until.filter(|until| *until > now).map(|until| until - now))
+}
+
+// Counts one more attempt against the key and returns its count. For an IP,
+// `last_failure_at` is when the next failure is forgiven from, less the interval.
+fn count_attempt(conn: &Connection, scope: &str, key: &str, now: i64) -> rusqlite::Result<i64> {
+    let sql = match scope {
+        IP_SCOPE => "INSERT INTO login_failures (scope, key, failures, last_failure_at) VALUES (?1, ?2, 1, ?3)
+             ON CONFLICT (scope, key) DO UPDATE SET
+                failures = failures - MIN((?3 - last_failure_at) / ?4, failures) + 1,
+                last_failure_at = CASE WHEN (?3 - last_failure_at) / ?4 >= failures THEN ?3
+                    ELSE last_failure_at + (?3 - last_failure_at) / ?4 * ?4 END
+             RETURNING failures",
+        _ => "INSERT INTO login_failures (scope, key, failures, last_failure_at) VALUES (?1, ?2, 1, ?3)
+             ON CONFLICT (scope, key) DO UPDATE SET
+                failures = CASE WHEN last_failure_at < ?3 - ?4 THEN 1 ELSE failures + 1 END,
+                last_failure_at = ?3
+             RETURNING failures",
+    };
+    let window = match scope {
+        IP_SCOPE => IP_FORGIVE_EVERY_SECONDS,
+        _ => EMAIL_FORGET_AFTER_SECONDS,
+    };
+    conn.query_row(sql, params![scope, key, now, window], |row| row.get(0))
+}
+
+// Counts the attempt up front, in one transaction with the lockout check, so a burst of
+// parallel requests can't all get past `retry_after` while the first password is still being
+// checked. Returns the seconds to wait instead when the email or IP is locked out.
+pub fn reserve_attempt(conn: &mut Connection, email: &str, ip: &str) -> rusqlite::Result<Option<i64>> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if let Some(seconds) = retry_after(&tx, email, ip)? {
+        return Ok(Some(seconds));
+    }
+    let now = chrono::Utc::now().timestamp();
+    for (scope, key, allowance) in counters(email, ip) {
+        let attempts = count_attempt(&tx, scope, &key, now)?;
+        // Held until the check is done: `record_failure` makes it a lockout, a success lifts it.
+        if let Some(seconds) = lockout_seconds(attempts, allowance) {
+            tx.execute(
+                "UPDATE login_failures SET locked_until = ?1 WHERE scope = ?2 AND key = ?3",
+                params![now + seconds, scope, key],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(None)
+}
+
+// The reserved attempt failed its check.
+pub fn record_failure(conn: &Connection, email: &str, ip: &str) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    for (scope, key, allowance) in counters(email, ip) {
+        let failures: Option<i64> = conn.query_row(
+            "SELECT failures FROM login_failures WHERE scope = ?1 AND key = ?2",
+            params![scope, key],
+            |row| row.get(0),
+        ).optional()?;
+        let Some(seconds) = failures.and_then(|failures| lockout_seconds(failures, allowance)) else {
+            continue;
+        };
+        let locked_until = now + seconds;
+        conn.execute(
+            "UPDATE login_failures SET locked_until = MAX(COALESCE(locked_until, 0), ?1) WHERE scope = ?2 AND key = ?3",
+            params![locked_until, scope, key],
+        )?;
+        // Failures checked in parallel share a lockout; only the first logs it.
+        let logged = conn.execute(
+            "INSERT INTO lockout_events (scope, key, failures, locked_until, created_at)
+             SELECT ?1, ?2, ?3, ?4, ?5
+             WHERE NOT EXISTS (SELECT 1 FROM lockout_events WHERE scope = ?1 AND key = ?2 AND locked_until > ?5)",
+            params![scope, key, failures, locked_until, now],
+        )?;
+        if logged == 0 {
+            continue;
+        }
+        println!("[WARN] Locked out {} {} for {}s after {} failed logins", scope, key, seconds, failures.unwrap_or_default());
+    }
+    Ok(())
+}
+
+// The login went through: the email starts over and the IP gets its attempt back.
+pub fn record_success(conn: &Connection, email: &str, ip: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM login_failures WHERE scope = ?1 AND key = ?2",
+        params![EMAIL_SCOPE, email_key(email)],
+    )?;
+    give_back(conn, IP_SCOPE, ip, IP_ALLOWANCE)
+}
+
+// The check passed but the login isn't finished (a second factor is due): the attempt is
+// given back to both counters, and the next step reserves its own.
+pub fn release(conn: &Connection, email: &str, ip: &str) -> rusqlite::Result<()> {
+    for (scope, key, allowance) in counters(email, ip) {
+        give_back(conn, scope, &key, allowance)?;
+    }
+    Ok(())
+}
+
+// Takes back an attempt that turned out to be right and lifts the hold it put on the key.
+// A lockout logged by a failure checked in parallel stays, unless giving the attempt back
+// brings the key under its allowance.
+fn give_back(conn: &Connection, scope: &str, key: &str, allowance: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE login_failures SET
+            failures = MAX(failures - 1, 0),
+            locked_until = CASE
+                WHEN failures - 1 < ?3 THEN NULL
+                WHEN EXISTS (SELECT 1 FROM lockout_events e WHERE e.scope = ?1 AND e.key = ?2 AND e.locked_until > ?4) THEN locked_until
+                ELSE NULL END
+         WHERE scope = ?1 AND key = ?2",
+        params![scope, key, allowance, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn recent_lockouts(conn: &Connection, limit: i64) -> rusqlite::Result<Vec<LockoutEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, scope, key, failures, locked_until, created_at
+         FROM lockout_events ORDER BY id DESC LIMIT ?1"
+    )?;
+    let events = stmt.query_map([limit], |row| {
+        Ok(LockoutEvent {
+            id: row.get(0)?,
+            scope: row.get(1)?,
+            key: row.get(2)?,
+            failures: row.get(3)?,
+            locked_until: row.get(4)?,
+            created_at: row.get(5)?,
+        })
+    })?;
+    events.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures(conn: &Connection, scope: &str, key: &str) -> (i64, Option<i64>) {
+        conn.query_row(
+            "SELECT failures, locked_until FROM login_failures WHERE scope = ?1 AND key = ?2",
+            params![scope, key],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap_or((0, None))
+    }
+
+    fn fail(conn: &mut Connection, email: &str, ip: &str) -> Option<i64> {
+        let refused = reserve_attempt(conn, email, ip).unwrap();
+        if refused.is_none() {
+            record_failure(conn, email, ip).unwrap();
+        }
+        refused
+    }
+
+    // Moves the key's last failure `seconds` into the past.
+    fn age(conn: &Connection, scope: &str, key: &str, seconds: i64) {
+        conn.execute(
+            "UPDATE login_failures SET last_failure_at = last_failure_at - ?1, locked_until = NULL WHERE scope = ?2 AND key = ?3",
+            params![seconds, scope, key],
+        ).unwrap();
+    }
+
+    #[test]
+    fn lockouts_double_up_to_an_hour() {
+        assert_eq!(lockout_seconds(4, 5), None);
+        assert_eq!(lockout_seconds(5, 5), Some(30));
+        assert_eq!(lockout_seconds(6, 5), Some(60));
+        assert_eq!(lockout_seconds(9, 5), Some(480));
+        assert_eq!(lockout_seconds(12, 5), Some(MAX_LOCKOUT_SECONDS));
+        assert_eq!(lockout_seconds(500, 5), Some(MAX_LOCKOUT_SECONDS));
+    }
+
+    #[test]
+    fn email_locks_after_its_allowance() {
+        let mut conn = crate::repository::test_connection();
+        for _ in 0..EMAIL_ALLOWANCE {
+            assert_eq!(fail(&mut conn, "Ada@Example.com ", "10.0.0.1"), None);
+        }
+        let wait = reserve_attempt(&mut conn, "ada@example.com", "10.0.0.2").unwrap().unwrap();
+        assert!(wait > 0 && wait <= BASE_LOCKOUT_SECONDS);
+        assert_eq!(retry_after(&conn, "other@example.com", "10.0.0.1").unwrap(), None);
+
+        let events = recent_lockouts(&conn, 10).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].scope.as_str(), events[0].key.as_str(), events[0].failures), ("email", "ada@example.com", EMAIL_ALLOWANCE));
+    }
+
+    #[test]
+    fn last_attempt_holds_the_key_only_while_checked() {
+        let mut conn = crate::repository::test_connection();
+        for _ in 1..EMAIL_ALLOWANCE {
+            fail(&mut conn, "ada@example.com", "10.0.0.1");
+        }
+        assert_eq!(reserve_attempt(&mut conn, "ada@example.com", "10.0.0.1").unwrap(), None);
+        // A parallel attempt waits for the check to finish.
+        assert!(reserve_attempt(&mut conn, "ada@example.com", "10.0.0.2").unwrap().is_some());
+
+        record_success(&conn, "ada@example.com", "10.0.0.1").unwrap();
+        assert_eq!(failures(&conn, EMAIL_SCOPE, "ada@example.com"), (0, None));
+        assert_eq!(retry_after(&conn, "ada@example.com", "10.0.0.1").unwrap(), None);
+        assert!(recent_lockouts(&conn, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn successful_ip_attempt_gives_back_its_count_and_hold() {
+        let mut conn = crate::repository::test_connection();
+        for i in 1..IP_ALLOWANCE {
+            fail(&mut conn, &format!("user{}@example.com", i), "10.0.0.1");
+        }
+        assert_eq!(reserve_attempt(&mut conn, "ada@example.com", "10.0.0.1").unwrap(), None);
+        assert!(failures(&conn, IP_SCOPE, "10.0.0.1").1.is_some());
+
+        record_success(&conn, "ada@example.com", "10.0.0.1").unwrap();
+        assert_eq!(failures(&conn, IP_SCOPE, "10.0.0.1"), (IP_ALLOWANCE - 1, None));
+        assert!(recent_lockouts(&conn, 10).unwrap().is_empty());
+
+        // The IP's earlier failures still count: the next wrong guess locks it.
+        assert_eq!(fail(&mut conn, "bob@example.com", "10.0.0.1"), None);
+        assert!(retry_after(&conn, "carol@example.com", "10.0.0.1").unwrap().is_some());
+    }
+
+    #[test]
+    fn release_keeps_earlier_failures() {
+        let mut conn = crate::repository::test_connection();
+        for _ in 1..EMAIL_ALLOWANCE {
+            fail(&mut conn, "ada@example.com", "10.0.0.1");
+        }
+        reserve_attempt(&mut conn, "ada@example.com", "10.0.0.1").unwrap();
+        release(&conn, "ada@example.com", "10.0.0.1").unwrap();
+
+        assert_eq!(failures(&conn, EMAIL_SCOPE, "ada@example.com"), (EMAIL_ALLOWANCE - 1, None));
+        assert_eq!(failures(&conn, IP_SCOPE, "10.0.0.1"), (EMAIL_ALLOWANCE - 1, None));
+        // The second factor gets one try before the lockout.
+        assert_eq!(fail(&mut conn, "ada@example.com", "10.0.0.1"), None);
+        assert!(fail(&mut conn, "ada@example.com", "10.0.0.1").is_some());
+    }
+
+    #[test]
+    fn right_password_past_the_allowance_does_not_lock() {
+        let mut conn = crate::repository::test_connection();
+        for _ in 0..EMAIL_ALLOWANCE {
+            fail(&mut conn, "ada@example.com", "10.0.0.1");
+        }
+        // Once the lockout has run out,
+        conn.execute("UPDATE login_failures SET locked_until = NULL", []).unwrap();
+        conn.execute("UPDATE lockout_events SET locked_until = 0", []).unwrap();
+
+        // past the allowance every attempt holds the key, and a right one lets go again.
+        assert_eq!(reserve_attempt(&mut conn, "ada@example.com", "10.0.0.1").unwrap(), None);
+        release(&conn, "ada@example.com", "10.0.0.1").unwrap();
+        assert_eq!(retry_after(&conn, "ada@example.com", "10.0.0.1").unwrap(), None);
+        assert_eq!(failures(&conn, EMAIL_SCOPE, "ada@example.com").0, EMAIL_ALLOWANCE);
+
+        // A wrong one is locked out for twice as long as the first lockout.
+        let wait = fail(&mut conn, "ada@example.com", "10.0.0.1");
+        assert_eq!(wait, None);
+        let wait = retry_after(&conn, "ada@example.com", "10.0.0.1").unwrap().unwrap();
+        assert!(wait > BASE_LOCKOUT_SECONDS && wait <= 2 * BASE_LOCKOUT_SECONDS);
+    }
+
+    #[test]
+    fn ip_failures_fade_one_at_a_time() {
+        let mut conn = crate::repository::test_connection();
+        for i in 0..10 {
+            fail(&mut conn, &format!("user{}@example.com", i), "10.0.0.1");
+        }
+        age(&conn, IP_SCOPE, "10.0.0.1", 3 * IP_FORGIVE_EVERY_SECONDS + 30);
+        fail(&mut conn, "ada@example.com", "10.0.0.1");
+        assert_eq!(failures(&conn, IP_SCOPE, "10.0.0.1").0, 8);
+
+        // The half interval left over still counts towards the next forgiven failure.
+        age(&conn, IP_SCOPE, "10.0.0.1", IP_FORGIVE_EVERY_SECONDS - 30);
+        fail(&mut conn, "ada@example.com", "10.0.0.1");
+        assert_eq!(failures(&conn, IP_SCOPE, "10.0.0.1").0, 8);
+
+        age(&conn, IP_SCOPE, "10.0.0.1", 100 * IP_FORGIVE_EVERY_SECONDS);
+        fail(&mut conn, "ada@example.com", "10.0.0.1");
+        assert_eq!(failures(&conn, IP_SCOPE, "10.0.0.1").0, 1);
+    }
+
+    #[test]
+    fn email_failures_are_forgotten_after_a_day() {
+        let mut conn = crate::repository::test_connection();
+        for _ in 1..EMAIL_ALLOWANCE {
+            fail(&mut conn, "ada@example.com", "10.0.0.1");
+        }
+        age(&conn, EMAIL_SCOPE, "ada@example.com", EMAIL_FORGET_AFTER_SECONDS - 60);
+        fail(&mut conn, "ada@example.com", "10.0.0.2");
+        assert_eq!(failures(&conn, EMAIL_SCOPE, "ada@example.com").0, EMAIL_ALLOWANCE);
+
+        age(&conn, EMAIL_SCOPE, "ada@example.com", EMAIL_FORGET_AFTER_SECONDS + 1);
+        fail(&mut conn, "ada@example.com", "10.0.0.3");
+        assert_eq!(failures(&conn, EMAIL_SCOPE, "ada@example.com").0, 1);
+    }
+}
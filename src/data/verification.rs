@@ -0,0 +1,96 @@
+use std::fmt;
+
+use rusqlite::{params, OptionalExtension};
+
+use crate::repository::AccountRepo;
+use crate::tokens;
+
+pub const VERIFICATION_TOKEN_TTL_SECONDS: i64 = 24 * 60 * 60;
+
+pub struct VerifiedEmail {
+    pub account_id: String,
+    pub email: String,
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    Invalid,
+    Expired,
+    EmailTaken,
+    Database(rusqlite::Error),
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationError::Invalid => write!(f, "Invalid verification token"),
+            VerificationError::Expired => write!(f, "Verification token has expired"),
+            VerificationError::EmailTaken => write!(f, "An account with this email already exists"),
+            VerificationError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for VerificationError {
+    fn from(e: rusqlite::Error) -> Self {
+        VerificationError::Database(e)
+    }
+}
+
+// Issues a token proving ownership of `email`, either the address the account registered
+// with or one it wants to switch to. Only the newest token for an account is usable, so any
+// earlier ones are consumed.
+pub fn issue(conn: &rusqlite::Connection, account_id: &str, email: &str) -> rusqlite::Result<String> {
+    let token = tokens::generate_opaque_token();
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE email_verifications SET consumed_at = ?1 WHERE account_id = ?2 AND consumed_at IS NULL",
+        params![now, account_id],
+    )?;
+    conn.execute(
+        "INSERT INTO email_verifications (token_hash, account_id, email, created_at, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![tokens::hash_opaque_token(&token), account_id, email, now, now + VERIFICATION_TOKEN_TTL_SECONDS],
+    )?;
+    Ok(token)
+}
+
+// Consumes the token and makes the address it was issued for the account's verified email.
+pub fn consume(conn: &mut rusqlite::Connection, token: &str) -> Result<VerifiedEmail, VerificationError> {
+    let tx = conn.transaction()?;
+    let now = chrono::Utc::now().timestamp();
+
+    let stored = tx.query_row(
+        "SELECT id, account_id, email, expires_at, consumed_at FROM email_verifications WHERE token_hash = ?1",
+        [tokens::hash_opaque_token(token)],
+        |row| Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, i64>(3)?,
+            row.get::<_, Option<i64>>(4)?,
+        )),
+    ).optional()?;
+
+    let (id, account_id, email, expires_at) = match stored {
+        Some((id, account_id, email, expires_at, None)) => (id, account_id, email, expires_at),
+        _ => return Err(VerificationError::Invalid),
+    };
+    if expires_at <= now {
+        return Err(VerificationError::Expired);
+    }
+
+    tx.execute("UPDATE email_verifications SET consumed_at = ?1 WHERE id = ?2", params![now, id])?;
+    match AccountRepo::new(&tx).confirm_email(&account_id, &email) {
+        Ok(0) => return Err(VerificationError::Invalid),
+        Ok(_) => {},
+        // Someone else registered the address while the change was pending.
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE => {
+            return Err(VerificationError::EmailTaken)
+        },
+        Err(e) => return Err(e.into()),
+    }
+    tx.commit()?;
+
+    Ok(VerifiedEmail { account_id, email })
+}
@@ -1,11 +1,28 @@
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{get, middleware, post, web, App, HttpResponse, HttpServer, Responder};
 use dotenv::dotenv;
 use actix_cors::Cors;
-use actix_web::http;
 
 #[path = "data/new_user.rs"] mod users;
 #[path = "utils/encrypt.rs"] mod enc;
-#[path = "data/posts.rs"] mod posts;
+#[path = "utils/tokens.rs"] mod tokens;
+#[path = "utils/auth.rs"] mod auth;
+#[path = "utils/errors.rs"] mod errors;
+#[path = "utils/validation.rs"] mod validation;
+#[path = "utils/mail.rs"] mod mail;
+#[path = "utils/oidc.rs"] mod oidc;
+#[path = "data/sessions.rs"] mod sessions;
+#[path = "data/login_events.rs"] mod login_events;
+#[path = "data/verification.rs"] mod verification;
+#[path = "data/password_resets.rs"] mod password_resets;
+#[path = "data/throttle.rs"] mod throttle;
+#[path = "data/two_factor.rs"] mod two_factor;
+#[path = "data/privacy.rs"] mod privacy;
+#[path = "data/onboarding.rs"] mod onboarding;
+#[path = "data/external_identities.rs"] mod external_identities;
+#[path = "data/questions.rs"] mod questions;
+#[path = "data/pool.rs"] mod db;
+#[path = "data/migrations.rs"] mod migrations;
+#[path = "data/repository/mod.rs"] mod repository;
 
 #[path = "utils/routes/messages.rs"] mod message_routes;
 #[path = "utils/routes/accounts.rs"] mod account_routes;
@@ -13,6 +30,11 @@ use actix_web::http;
 #[path = "utils/routes/misc.rs"] mod misc_routes;
 
 #[path = "utils/routes/applications.rs"] mod application_routes;
+#[path = "utils/routes/admin.rs"] mod admin_routes;
+#[path = "utils/routes/two_factor.rs"] mod two_factor_routes;
+#[path = "utils/routes/sessions.rs"] mod session_routes;
+#[path = "utils/routes/profile.rs"] mod profile_routes;
+#[path = "utils/routes/oidc.rs"] mod oidc_routes;
 
 #[get("/")]
 async fn hello() -> impl Responder {
@@ -31,10 +53,43 @@ async fn manual_hello() -> impl Responder {
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
-    let _ = init_database();
+    let pool = db::init_pool(&db::database_path())
+        .map_err(|e| std::io::Error::other(format!("Failed to open database: {}", e)))?;
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        return migrate_command(&pool, args.get(2).map(String::as_str));
+    }
+    run_migrations(&pool)?;
+    enc::HashConfig::from_env()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    // Made now rather than on the first login for an unknown email, which would stand out by taking longer.
+    enc::dummy_hash()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    tokens::check_secret()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    if args.get(1).map(String::as_str) == Some("create-admin") {
+        return create_admin_command(&pool, &args[2..]);
+    }
+    bootstrap_admin(&pool)?;
+    let mailer = mail::from_env()
+        .map_err(|e| std::io::Error::other(format!("Failed to configure mail: {}", e)))?;
+    let oidc_providers = web::Data::new(oidc::Providers::from_env()
+        .map_err(|e| std::io::Error::other(e.to_string()))?);
+    if !oidc_providers.names().is_empty() {
+        println!("[LOG] Sign-in providers enabled: {}", oidc_providers.names().join(", "));
+    }
+
     println!("Started RESTful API on \nPublic: https://api.leafdevs.xyz/ \nPrivate: http://127.0.0.1:8080/ ");
     HttpServer::new(move || {
         App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(mailer.clone()))
+            .app_data(oidc_providers.clone())
+            .app_data(web::JsonConfig::default().content_type_required(false).error_handler(errors::json_error))
+            .app_data(web::PathConfig::default().error_handler(errors::path_error))
+            .app_data(web::QueryConfig::default().error_handler(errors::query_error))
+            .wrap(middleware::from_fn(errors::request_id))
             .wrap(
                 Cors::default()
                     .allowed_origin("http://localhost:5173")
@@ -42,11 +97,16 @@ async fn main() -> std::io::Result<()> {
                     .allowed_origin("https://jobs.leafdevs.xyz")
                     .allowed_origin("https://api.leafdevs.xyz")
                     .allowed_origin("http://127.0.0.1:5173")
-                    .allowed_methods(vec!["GET", "POST", "OPTIONS", "DELETE", "PUT"])
+                    .allowed_methods(vec!["GET", "POST", "OPTIONS", "DELETE", "PUT", "PATCH"])
                     .allowed_headers(vec![
                         actix_web::http::header::AUTHORIZATION,
                         actix_web::http::header::ACCEPT,
-                        actix_web::http::header::CONTENT_TYPE
+                        actix_web::http::header::CONTENT_TYPE,
+                        actix_web::http::header::HeaderName::from_static(errors::REQUEST_ID_HEADER)
+                    ])
+                    .expose_headers(vec![
+                        actix_web::http::header::HeaderName::from_static(errors::REQUEST_ID_HEADER),
+                        actix_web::http::header::RETRY_AFTER
                     ])
                     .max_age(3600),
             )
@@ -57,16 +117,45 @@ async fn main() -> std::io::Result<()> {
             .service(account_routes::get_user)
             .service(account_routes::login_account)
             .service(account_routes::register_account)
+            .service(account_routes::refresh_session)
+            .service(account_routes::logout)
+            .service(account_routes::verify_email)
+            .service(account_routes::resend_verification)
+            .service(account_routes::forgot_password)
+            .service(account_routes::reset_password)
+            .service(account_routes::change_password)
+            .service(account_routes::change_email)
+            .service(account_routes::complete_two_factor_login)
+            .service(oidc_routes::list_providers)
+            .service(oidc_routes::start_oidc_login)
+            .service(oidc_routes::oidc_callback)
+            .service(oidc_routes::start_identity_flow)
+            .service(oidc_routes::identity_callback)
+            .service(account_routes::deactivate_account)
+            .service(account_routes::delete_account)
+            .service(two_factor_routes::setup)
+            .service(two_factor_routes::confirm)
+            .service(two_factor_routes::disable)
+            .service(session_routes::list_sessions)
+            .service(session_routes::revoke_session)
+            .service(session_routes::login_history)
+            .service(profile_routes::get_profile)
+            .service(profile_routes::update_profile)
             .service(account_routes::update_employer_agreements)
+            .service(account_routes::update_student_forms)
             .service(account_routes::get_total_employers)
             .service(account_routes::get_total_users)
             .service(account_routes::get_all_users_without_private_information_leaked)
+            .service(account_routes::get_public_profile)
             .service(message_routes::get_messages)
             .service(message_routes::get_conversations)
             .service(message_routes::send_message)
 
             // Post Routes
             .service(post_routes::get_posts)
+            .service(post_routes::get_popular_tags)
+            .service(post_routes::get_popular_skills)
+            .service(post_routes::get_post)
             .service(post_routes::create_post)
             .service(post_routes::accept_post)
             .service(post_routes::reject_post)
@@ -80,7 +169,15 @@ async fn main() -> std::io::Result<()> {
             .service(application_routes::get_received_applications)
             .service(application_routes::get_submitted_applications)
             .service(application_routes::update_application_status)
-            
+
+            // Admin Routes
+            .service(admin_routes::update_user_role)
+            .service(admin_routes::suspend_user)
+            .service(admin_routes::reinstate_user)
+            .service(admin_routes::delete_user)
+            .service(admin_routes::get_lockouts)
+            .service(admin_routes::get_two_factor_policy)
+            .service(admin_routes::update_two_factor_policy)
 
             // Misc Routes
 
@@ -92,6 +189,7 @@ async fn main() -> std::io::Result<()> {
             // Post Routes
 
             .route("/hey", web::get().to(manual_hello))
+            .default_service(web::route().to(errors::route_not_found))
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -99,84 +197,126 @@ async fn main() -> std::io::Result<()> {
 }
 
 
-// Setup Database Connection
-
-fn init_database() -> rusqlite::Result<()> {
-    let conn = rusqlite::Connection::open("fbla.db")?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            sender_id INTEGER NOT NULL,
-            receiver_id INTEGER NOT NULL,
-            content TEXT NOT NULL,
-            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
-            read BOOLEAN DEFAULT FALSE,
-            message_type TEXT,
-            file_url TEXT,
-            FOREIGN KEY (sender_id) REFERENCES accounts(id),
-            FOREIGN KEY (receiver_id) REFERENCES accounts(id)
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS accounts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            email VARCHAR(255) NOT NULL UNIQUE,
-            password VARCHAR(255) NOT NULL,
-            unique_id VARCHAR(255) NOT NULL UNIQUE,
-            first_name VARCHAR(255) NOT NULL,
-            last_name VARCHAR(255) NOT NULL,
-            account_type VARCHAR(50) NOT NULL,
-            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
-            last_login DATETIME,
-            status VARCHAR(50) NOT NULL DEFAULT 'active',
-            profile TEXT NOT NULL,
-            CONSTRAINT email_unique UNIQUE (email),
-            CONSTRAINT uuid_unique UNIQUE (unique_id)
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS posts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            title TEXT NOT NULL,
-            description TEXT NOT NULL,
-            tags TEXT NOT NULL,
-            documents TEXT NOT NULL,
-            tips TEXT NOT NULL,
-            skills TEXT NOT NULL,
-            experience TEXT NOT NULL,
-            jobtype TEXT NOT NULL,
-            location TEXT NOT NULL,
-            date TEXT NOT NULL,
-            questions TEXT NOT NULL,
-            company_name TEXT NOT NULL,
-            employer_id TEXT NOT NULL,
-            status TEXT NOT NULL DEFAULT 'Pending' CHECK (status IN ('Accepted', 'Pending')),
-            FOREIGN KEY (employer_id) REFERENCES accounts (unique_id)
-        )",
-        [],
-    )?;
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS applications (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            post_id INTEGER NOT NULL,
-            applicant_id TEXT NOT NULL,
-            employer_id TEXT NOT NULL,
-            status TEXT NOT NULL,
-            answers TEXT NOT NULL,
-            created_at TEXT NOT NULL,
-            updated_at TEXT NOT NULL,
-            FOREIGN KEY (post_id) REFERENCES posts (id),
-            FOREIGN KEY (applicant_id) REFERENCES accounts (unique_id),
-            FOREIGN KEY (employer_id) REFERENCES accounts (unique_id)
-        )",
-        [],
-    )?;
+// Database Migrations
+
+fn run_migrations(pool: &db::DbPool) -> std::io::Result<()> {
+    let mut conn = pool.get()
+        .map_err(|e| std::io::Error::other(format!("Failed to open database: {}", e)))?;
+    match migrations::run_pending(&mut conn) {
+        Ok(ran) if ran.is_empty() => println!("[LOG] Database schema is up to date"),
+        Ok(ran) => println!("[LOG] Applied {} migration(s)", ran.len()),
+        Err(e) => {
+            println!("[ERROR] Migration failed, refusing to start: {}", e);
+            return Err(std::io::Error::other(format!("Migration failed: {}", e)));
+        }
+    }
+    Ok(())
+}
+
+// `backend migrate` applies pending migrations and exits; `backend migrate status` lists them.
+fn migrate_command(pool: &db::DbPool, command: Option<&str>) -> std::io::Result<()> {
+    match command {
+        None | Some("up") => run_migrations(pool),
+        Some("status") => {
+            let conn = pool.get()
+                .map_err(|e| std::io::Error::other(format!("Failed to open database: {}", e)))?;
+            let applied = migrations::applied(&conn)
+                .map_err(|e| std::io::Error::other(format!("Failed to read migrations: {}", e)))?;
+            for migration in migrations::MIGRATIONS {
+                match applied.iter().find(|a| a.version == migration.version) {
+                    Some(a) => println!("{:>4}  {:<32} applied at {}", a.version, a.name, a.applied_at),
+                    None => println!("{:>4}  {:<32} pending", migration.version, migration.name),
+                }
+            }
+            Ok(())
+        },
+        Some(other) => Err(std::io::Error::other(format!("Unknown migrate command: {} (expected `up` or `status`)", other)))
+    }
+}
+
+
+// Administrator Provisioning
+
+// Creates an administrator account, refusing to touch an existing account with the same email.
+fn create_admin(pool: &db::DbPool, email: &str, password: &str, first_name: &str, last_name: &str) -> std::io::Result<String> {
+    use validator::ValidateEmail;
+
+    if !email.validate_email() {
+        return Err(std::io::Error::other(format!("Invalid email address: {}", email)));
+    }
+    if password.len() < 8 {
+        return Err(std::io::Error::other("Administrator password must be at least 8 characters"));
+    }
+
+    let conn = pool.get()
+        .map_err(|e| std::io::Error::other(format!("Failed to open database: {}", e)))?;
+    let accounts = repository::AccountRepo::new(&conn);
+    let existing = accounts.find_by_email(email)
+        .map_err(|e| std::io::Error::other(format!("Failed to look up account: {}", e)))?;
+    if existing.is_some() {
+        return Err(std::io::Error::other(format!(
+            "An account with email {} already exists; promote it with PUT /api/v1/admin/users/{{id}}/role instead", email
+        )));
+    }
+
+    let password_hash = enc::hash_password(password)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let new_user = users::NewUser::new(
+        email.to_string(),
+        password_hash,
+        first_name.to_string(),
+        last_name.to_string(),
+        users::AccountType::Administrator
+    );
+    // The operator vouches for the address, so it counts as verified and the account starts out active.
+    accounts.insert(&new_user)
+        .and_then(|_| accounts.confirm_email(&new_user.unique_id, &new_user.email))
+        .map_err(|e| std::io::Error::other(format!("Failed to create administrator: {}", e)))?;
+    Ok(new_user.unique_id)
+}
+
+// `backend create-admin <email> <first_name> <last_name>` reads the password from ADMIN_PASSWORD,
+// or from the first line of stdin, so it never shows up in the process list.
+fn create_admin_command(pool: &db::DbPool, args: &[String]) -> std::io::Result<()> {
+    let (email, first_name, last_name) = match args {
+        [email, first_name, last_name] => (email, first_name, last_name),
+        _ => return Err(std::io::Error::other("Usage: backend create-admin <email> <first_name> <last_name>"))
+    };
+    let password = match std::env::var("ADMIN_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => {
+            println!("Password for {}:", email);
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    let unique_id = create_admin(pool, email, &password, first_name, last_name)?;
+    println!("[LOG] Created administrator {} ({})", email, unique_id);
+    Ok(())
+}
+
+// When ADMIN_EMAIL and ADMIN_PASSWORD are set and no administrator exists yet, create one on startup.
+fn bootstrap_admin(pool: &db::DbPool) -> std::io::Result<()> {
+    let (email, password) = match (std::env::var("ADMIN_EMAIL"), std::env::var("ADMIN_PASSWORD")) {
+        (Ok(email), Ok(password)) => (email, password),
+        _ => return Ok(())
+    };
+
+    let conn = pool.get()
+        .map_err(|e| std::io::Error::other(format!("Failed to open database: {}", e)))?;
+    let admins = repository::AccountRepo::new(&conn).count(Some(users::AccountType::Administrator))
+        .map_err(|e| std::io::Error::other(format!("Failed to count administrators: {}", e)))?;
+    drop(conn);
+    if admins > 0 {
+        return Ok(());
+    }
+
+    match create_admin(pool, &email, &password, "Site", "Administrator") {
+        Ok(unique_id) => println!("[LOG] Bootstrapped administrator {} ({})", email, unique_id),
+        Err(e) => println!("[WARN] Could not bootstrap administrator from ADMIN_EMAIL: {}", e),
+    }
     Ok(())
 }
 
@@ -0,0 +1,217 @@
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, http::header::USER_AGENT, web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::accounts::Account;
+use crate::repository::AccountRepo;
+use crate::sessions::{self, ClientInfo};
+use crate::tokens::{self, TokenError};
+use crate::two_factor;
+use crate::users::{AccountStatus, AccountType};
+
+// The address failed logins are throttled against. Forwarded headers are only believed when
+// TRUST_PROXY_HEADERS is set, since any client can send them.
+pub fn client_ip(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    let ip = match std::env::var("TRUST_PROXY_HEADERS").as_deref() {
+        Ok("true") | Ok("1") => info.realip_remote_addr(),
+        _ => info.peer_addr(),
+    };
+    ip.unwrap_or("unknown").to_string()
+}
+
+// Recorded with each session and login. Overlong user agents are cut short rather than stored whole.
+pub fn client_info(req: &HttpRequest) -> ClientInfo {
+    let user_agent = req.headers()
+        .get(USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .map(|ua| ua.chars().take(512).collect());
+    ClientInfo { ip: client_ip(req), user_agent }
+}
+
+// Suspended accounts are turned away everywhere. Deactivated ones are too, until their owner
+// signs in with their password again.
+pub fn check_status(account: &Account) -> Result<(), ApiError> {
+    match account.status {
+        AccountStatus::Suspended => Err(ApiError::AccountSuspended(account.status_reason.clone())),
+        AccountStatus::Inactive => Err(ApiError::AccountDeactivated),
+        AccountStatus::Active | AccountStatus::PendingVerification => Ok(()),
+    }
+}
+
+// The account behind a verified access token, loaded fresh from the database on every request.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub id: i64,
+    pub unique_id: String,
+    // The session (refresh token family) the access token was issued for.
+    pub session_id: String,
+    pub account_type: AccountType,
+    pub status: AccountStatus,
+    pub two_factor_enabled: bool,
+    // Set when 2FA is required for this account type but isn't enrolled yet.
+    pub two_factor_setup_required: bool,
+}
+
+// Role guards: extracting one of these rejects the request with 403 unless the account has that role.
+pub struct StudentUser(pub AuthenticatedUser);
+pub struct EmployerUser(pub AuthenticatedUser);
+pub struct AdminUser(pub AuthenticatedUser);
+
+// Every extractor above refuses accounts that still have to enroll in required two-factor
+// authentication. This one doesn't, so it is what the enrollment endpoints take.
+pub struct EnrollingUser(pub AuthenticatedUser);
+
+impl AuthenticatedUser {
+    pub fn is_admin(&self) -> bool {
+        self.account_type == AccountType::Administrator
+    }
+
+    // Posting and applying are held back until the account's email address is confirmed.
+    pub fn require_verified_email(&self) -> Result<(), ApiError> {
+        match self.status {
+            AccountStatus::PendingVerification => Err(ApiError::EmailNotVerified),
+            _ => Ok(()),
+        }
+    }
+
+    async fn load(req: HttpRequest, enforce_two_factor: bool) -> Result<Self, ApiError> {
+        let claims = tokens::authenticate(&req)?;
+        let pool = match req.app_data::<web::Data<DbPool>>() {
+            Some(pool) => pool.clone(),
+            None => return Err(ApiError::Internal("Database pool is not registered".to_string())),
+        };
+        let session_id = claims.sid.clone();
+        let (account, two_factor_enabled, two_factor_required) = db::run(&pool, move |conn| {
+            let account = match AccountRepo::new(conn).find_by_unique_id(&claims.sub)? {
+                Some(account) => account,
+                None => return Ok(Err(TokenError::Invalid))
+            };
+            // Signing a session out (or resetting the password) must not leave its access tokens usable.
+            if !sessions::is_active(conn, &account.unique_id, &claims.sid)? {
+                return Ok(Err(TokenError::Revoked));
+            }
+            let enabled = two_factor::is_enabled(conn, &account.unique_id)?;
+            let required = two_factor::is_required(conn, account.account_type)?;
+            Ok(Ok((account, enabled, required)))
+        })
+            .await?
+            .map_err(ApiError::Unauthenticated)?;
+        check_status(&account)?;
+        let two_factor_setup_required = two_factor_required && !two_factor_enabled;
+        if enforce_two_factor && two_factor_setup_required {
+            return Err(ApiError::TwoFactorSetupRequired);
+        }
+        Ok(AuthenticatedUser {
+            id: account.id,
+            unique_id: account.unique_id,
+            session_id,
+            account_type: account.account_type,
+            status: account.status,
+            two_factor_enabled,
+            two_factor_setup_required,
+        })
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        Box::pin(AuthenticatedUser::load(req.clone(), true))
+    }
+}
+
+impl FromRequest for StudentUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::load(req.clone(), true);
+        Box::pin(async move {
+            let user = user.await?;
+            match user.account_type {
+                AccountType::Student => Ok(StudentUser(user)),
+                _ => Err(ApiError::Forbidden("Only students can perform this action")),
+            }
+        })
+    }
+}
+
+impl FromRequest for EmployerUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::load(req.clone(), true);
+        Box::pin(async move {
+            let user = user.await?;
+            match user.account_type {
+                AccountType::Employer => Ok(EmployerUser(user)),
+                _ => Err(ApiError::Forbidden("Only employers can perform this action")),
+            }
+        })
+    }
+}
+
+impl FromRequest for AdminUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::load(req.clone(), true);
+        Box::pin(async move {
+            let user = user.await?;
+            match user.is_admin() {
+                true => Ok(AdminUser(user)),
+                false => Err(ApiError::Forbidden("Only administrators can perform this action")),
+            }
+        })
+    }
+}
+
+impl FromRequest for EnrollingUser {
+    type Error = ApiError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::load(req.clone(), false);
+        Box::pin(async move { Ok(EnrollingUser(user.await?)) })
+    }
+}
+
+impl Deref for StudentUser {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.0
+    }
+}
+
+impl Deref for EmployerUser {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.0
+    }
+}
+
+impl Deref for AdminUser {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.0
+    }
+}
+
+impl Deref for EnrollingUser {
+    type Target = AuthenticatedUser;
+
+    fn deref(&self) -> &AuthenticatedUser {
+        &self.0
+    }
+}
@@ -1,35 +1,133 @@
-use std::{env};
+use std::sync::OnceLock;
+use std::{env, fmt};
 use argonautica::{Hasher, Verifier};
 
-pub fn hash_password(password: &str) -> String {
-    let hash_secret = env::var("HASH_SECRET").unwrap(); // Unwrap for simplicity (no error handling)
-    
+use actix_web::web;
+
+// Argon2id cost parameters. Defaults follow the OWASP baseline (19 MiB, 2 passes, 1 lane)
+// and can be raised through the environment; existing hashes are upgraded on next login.
+pub struct HashConfig {
+    iterations: u32,
+    memory_kib: u32,
+    lanes: u32,
+    hash_len: u32,
+    secret: String,
+}
+
+#[derive(Debug)]
+pub enum HashError {
+    MissingSecret,
+    InvalidConfig(String),
+    Hashing(argonautica::Error),
+    Blocking,
+}
+
+impl fmt::Display for HashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::MissingSecret => write!(f, "HASH_SECRET is not configured"),
+            HashError::InvalidConfig(e) => write!(f, "Invalid password hashing configuration: {}", e),
+            HashError::Hashing(e) => write!(f, "Password hashing failed: {}", e),
+            HashError::Blocking => write!(f, "Password hashing task was cancelled"),
+        }
+    }
+}
+
+fn env_u32(name: &str, default: u32) -> Result<u32, HashError> {
+    match env::var(name) {
+        Ok(value) => value.parse().map_err(|_| HashError::InvalidConfig(format!("{} must be a positive integer", name))),
+        Err(_) => Ok(default),
+    }
+}
+
+impl HashConfig {
+    pub fn from_env() -> Result<Self, HashError> {
+        let secret = env::var("HASH_SECRET").map_err(|_| HashError::MissingSecret)?;
+        let config = HashConfig {
+            iterations: env_u32("ARGON2_ITERATIONS", 2)?,
+            memory_kib: env_u32("ARGON2_MEMORY_KIB", 19 * 1024)?,
+            lanes: env_u32("ARGON2_LANES", 1)?,
+            hash_len: env_u32("ARGON2_HASH_LEN", 32)?,
+            secret,
+        };
+        if config.iterations == 0 || config.lanes == 0 {
+            return Err(HashError::InvalidConfig("ARGON2_ITERATIONS and ARGON2_LANES must be at least 1".to_string()));
+        }
+        if config.memory_kib < 8 * config.lanes {
+            return Err(HashError::InvalidConfig("ARGON2_MEMORY_KIB must be at least 8 per lane".to_string()));
+        }
+        if config.hash_len < 16 {
+            return Err(HashError::InvalidConfig("ARGON2_HASH_LEN must be at least 16".to_string()));
+        }
+        Ok(config)
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, HashError> {
+    let config = HashConfig::from_env()?;
     let mut hasher = Hasher::default();
-    let hash = hasher
+    hasher
         .with_password(password)
-        .with_secret_key(&hash_secret)
-        // Recommended Argon2 parameters (adjust based on your security requirements)
-        .configure_iterations(30)      // 3,000 iterations (more iterations = better security)
-        .configure_memory_size(4096)    // 64 MB memory (higher is more secure, adjust based on hardware)
-        .configure_lanes(8)               // 8 lanes (higher = better parallelization, use more CPU)
-        .configure_hash_len(256)          // 256-byte hash (standard for high security)
+        .with_secret_key(&config.secret)
+        .configure_iterations(config.iterations)
+        .configure_memory_size(config.memory_kib)
+        .configure_lanes(config.lanes)
+        .configure_threads(config.lanes)
+        .configure_hash_len(config.hash_len)
         .configure_variant(argonautica::config::Variant::Argon2id)
         .hash()
-        .unwrap(); // Unwrap here as well, assuming no error
-
-    hash
+        .map_err(HashError::Hashing)
 }
 
-
-pub fn verify_password(password: &str, hash: &str) -> rusqlite::Result<bool> {
-    let hash_secret = env::var("HASH_SECRET").map_err(|_| rusqlite::Error::InvalidQuery)?;
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, HashError> {
+    let hash_secret = env::var("HASH_SECRET").map_err(|_| HashError::MissingSecret)?;
     let mut verifier = Verifier::default();
-    print!("{hash}");
-    let is_valid = verifier
+    verifier
         .with_hash(hash)
         .with_password(password)
         .with_secret_key(hash_secret)
         .verify()
-        .map_err(|_| rusqlite::Error::InvalidQuery)?;
-    Ok(is_valid)
-}
\ No newline at end of file
+        .map_err(HashError::Hashing)
+}
+
+// True when `hash` was made with other parameters than the current configuration, read from
+// its PHC string: $argon2id$v=19$m=<kib>,t=<iterations>,p=<lanes>$<salt>$<hash>
+pub fn needs_rehash(hash: &str) -> Result<bool, HashError> {
+    let config = HashConfig::from_env()?;
+    let parts: Vec<&str> = hash.split('$').collect();
+    let (variant, params, digest) = match parts.as_slice() {
+        ["", variant, _version, params, _salt, digest] => (*variant, *params, *digest),
+        _ => return Ok(true),
+    };
+
+    let expected = format!("m={},t={},p={}", config.memory_kib, config.iterations, config.lanes);
+    // The digest is unpadded base64, so every 4 characters hold 3 bytes.
+    let digest_len = digest.len() * 3 / 4;
+    Ok(variant != "argon2id" || params != expected || digest_len != config.hash_len as usize)
+}
+
+// Checked against when there is no real hash (unknown email, account without a password), so
+// those logins take as long as a wrong password and don't reveal which emails are registered.
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+pub fn dummy_hash() -> Result<&'static str, HashError> {
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash);
+    }
+    let hash = hash_password(&uuid::Uuid::new_v4().to_string())?;
+    Ok(DUMMY_HASH.get_or_init(|| hash))
+}
+
+// Hashing is deliberately slow, so the async handlers run it on the blocking pool.
+pub async fn hash(password: String) -> Result<String, HashError> {
+    web::block(move || hash_password(&password)).await.map_err(|_| HashError::Blocking)?
+}
+
+pub async fn verify(password: String, hash: String) -> Result<bool, HashError> {
+    web::block(move || verify_password(&password, &hash)).await.map_err(|_| HashError::Blocking)?
+}
+
+// Takes as long as `verify` and never matches.
+pub async fn verify_dummy(password: String) -> Result<bool, HashError> {
+    web::block(move || verify_password(&password, dummy_hash()?).map(|_| false)).await.map_err(|_| HashError::Blocking)?
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, ResponseError};
+
+use crate::db::DbError;
+use crate::enc::HashError;
+use crate::oidc::OidcError;
+use crate::password_resets::ResetError;
+use crate::sessions::RefreshError;
+use crate::tokens::TokenError;
+use crate::two_factor::TwoFactorError;
+use crate::validation::FieldError;
+use crate::verification::VerificationError;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Every failed request is answered with the same envelope:
+// {"success": false, "error": <message>, "code": <stable code>, "request_id": <id>},
+// plus a `fields` list for validation failures.
+// Only the message written here reaches the client; anything carried for
+// debugging (database errors, internal details) is logged against the request id.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Validation(Vec<FieldError>),
+    Unauthenticated(TokenError),
+    InvalidCredentials,
+    InvalidRefreshToken(RefreshError),
+    InvalidVerificationToken(VerificationError),
+    InvalidResetToken(ResetError),
+    EmailNotVerified,
+    TwoFactor(TwoFactorError),
+    TwoFactorSetupRequired,
+    AccountSuspended(Option<String>),
+    AccountDeactivated,
+    Oidc(OidcError),
+    TooManyAttempts(i64),
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    Conflict(&'static str),
+    Database(DbError),
+    Internal(String),
+}
+
+impl ApiError {
+    // Machine-readable and stable; clients should branch on this rather than the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Unauthenticated(TokenError::MissingToken) => "missing_token",
+            ApiError::Unauthenticated(TokenError::Expired) => "token_expired",
+            ApiError::Unauthenticated(TokenError::Revoked) => "session_revoked",
+            ApiError::Unauthenticated(TokenError::MissingSecret | TokenError::Signing) => "internal_error",
+            ApiError::Unauthenticated(TokenError::Invalid) => "invalid_token",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::InvalidRefreshToken(RefreshError::Expired) => "refresh_token_expired",
+            ApiError::InvalidRefreshToken(RefreshError::Reused) => "refresh_token_reused",
+            ApiError::InvalidRefreshToken(RefreshError::Database(_)) => "internal_error",
+            ApiError::InvalidRefreshToken(RefreshError::Invalid) => "invalid_refresh_token",
+            ApiError::InvalidVerificationToken(VerificationError::Expired) => "verification_token_expired",
+            ApiError::InvalidVerificationToken(VerificationError::Database(_)) => "internal_error",
+            ApiError::InvalidVerificationToken(VerificationError::EmailTaken) => "conflict",
+            ApiError::InvalidVerificationToken(VerificationError::Invalid) => "invalid_verification_token",
+            ApiError::InvalidResetToken(ResetError::Expired) => "reset_token_expired",
+            ApiError::InvalidResetToken(ResetError::Database(_)) => "internal_error",
+            ApiError::InvalidResetToken(ResetError::Invalid) => "invalid_reset_token",
+            ApiError::EmailNotVerified => "email_not_verified",
+            ApiError::TwoFactor(TwoFactorError::InvalidCode) => "invalid_two_factor_code",
+            ApiError::TwoFactor(TwoFactorError::InvalidChallenge) => "invalid_challenge",
+            ApiError::TwoFactor(TwoFactorError::ChallengeExpired) => "challenge_expired",
+            ApiError::TwoFactor(TwoFactorError::Database(_)) => "internal_error",
+            ApiError::TwoFactorSetupRequired => "two_factor_setup_required",
+            ApiError::AccountSuspended(_) => "account_suspended",
+            ApiError::AccountDeactivated => "account_deactivated",
+            ApiError::Oidc(OidcError::InvalidState) => "invalid_oidc_state",
+            ApiError::Oidc(OidcError::Exchange(_)) => "oidc_exchange_failed",
+            ApiError::Oidc(OidcError::InvalidIdToken(_)) => "invalid_id_token",
+            ApiError::Oidc(OidcError::Unavailable(_)) => "oidc_provider_unavailable",
+            ApiError::Oidc(OidcError::UnverifiedEmail) => "oidc_email_not_verified",
+            ApiError::Oidc(OidcError::Config(_)) => "internal_error",
+            ApiError::TooManyAttempts(_) => "too_many_attempts",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    // The full story for the server log; never sent to clients.
+    fn detail(&self) -> String {
+        match self {
+            ApiError::Unauthenticated(e) => e.to_string(),
+            ApiError::InvalidRefreshToken(e) => e.to_string(),
+            ApiError::InvalidVerificationToken(e) => e.to_string(),
+            ApiError::InvalidResetToken(e) => e.to_string(),
+            ApiError::TwoFactor(e) => e.to_string(),
+            ApiError::Oidc(e) => e.to_string(),
+            ApiError::Database(e) => e.to_string(),
+            ApiError::Internal(detail) => detail.clone(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message) => write!(f, "{}", message),
+            ApiError::Validation(_) => write!(f, "Request validation failed"),
+            ApiError::Unauthenticated(TokenError::MissingSecret | TokenError::Signing) => write!(f, "Internal server error"),
+            ApiError::Unauthenticated(e) => write!(f, "{}", e),
+            ApiError::InvalidCredentials => write!(f, "Invalid email or password"),
+            ApiError::InvalidRefreshToken(RefreshError::Database(_)) => write!(f, "Internal server error"),
+            ApiError::InvalidRefreshToken(e) => write!(f, "{}", e),
+            ApiError::InvalidVerificationToken(VerificationError::Database(_)) => write!(f, "Internal server error"),
+            ApiError::InvalidVerificationToken(e) => write!(f, "{}", e),
+            ApiError::InvalidResetToken(ResetError::Database(_)) => write!(f, "Internal server error"),
+            ApiError::InvalidResetToken(e) => write!(f, "{}", e),
+            ApiError::EmailNotVerified => write!(f, "Verify your email address before continuing"),
+            ApiError::TwoFactor(TwoFactorError::Database(_)) => write!(f, "Internal server error"),
+            ApiError::TwoFactor(e) => write!(f, "{}", e),
+            ApiError::TwoFactorSetupRequired => write!(f, "Set up two-factor authentication to continue"),
+            ApiError::AccountSuspended(Some(reason)) => write!(f, "This account has been suspended: {}", reason),
+            ApiError::AccountSuspended(None) => write!(f, "This account has been suspended"),
+            ApiError::AccountDeactivated => write!(f, "This account has been deactivated, sign in again to reactivate it"),
+            ApiError::Oidc(OidcError::Config(_)) => write!(f, "Internal server error"),
+            ApiError::Oidc(OidcError::Exchange(_)) => write!(f, "The sign-in provider rejected the authorization code"),
+            ApiError::Oidc(OidcError::Unavailable(_)) => write!(f, "The sign-in provider could not be reached"),
+            ApiError::Oidc(e) => write!(f, "{}", e),
+            ApiError::TooManyAttempts(seconds) => write!(f, "Too many failed attempts, try again in {} seconds", seconds),
+            ApiError::Forbidden(message) | ApiError::NotFound(message) | ApiError::Conflict(message) => write!(f, "{}", message),
+            ApiError::Database(_) | ApiError::Internal(_) => write!(f, "Internal server error"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthenticated(TokenError::MissingSecret | TokenError::Signing) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::InvalidRefreshToken(RefreshError::Database(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::InvalidVerificationToken(VerificationError::Database(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::InvalidVerificationToken(VerificationError::EmailTaken) => StatusCode::CONFLICT,
+            ApiError::InvalidResetToken(ResetError::Database(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::TwoFactor(TwoFactorError::Database(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::TwoFactor(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Unauthenticated(_) | ApiError::InvalidCredentials | ApiError::InvalidRefreshToken(_) => StatusCode::UNAUTHORIZED,
+            ApiError::InvalidVerificationToken(_) | ApiError::InvalidResetToken(_) => StatusCode::BAD_REQUEST,
+            ApiError::EmailNotVerified | ApiError::TwoFactorSetupRequired | ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::AccountSuspended(_) | ApiError::AccountDeactivated => StatusCode::FORBIDDEN,
+            ApiError::Oidc(OidcError::InvalidState | OidcError::Exchange(_)) => StatusCode::BAD_REQUEST,
+            ApiError::Oidc(OidcError::InvalidIdToken(_)) => StatusCode::UNAUTHORIZED,
+            ApiError::Oidc(OidcError::UnverifiedEmail) => StatusCode::FORBIDDEN,
+            ApiError::Oidc(OidcError::Unavailable(_)) => StatusCode::BAD_GATEWAY,
+            ApiError::Oidc(OidcError::Config(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let request_id = current_request_id();
+        if self.status_code().is_server_error() {
+            println!("[ERROR] [{}] {}", request_id, self.detail());
+        }
+        let mut body = serde_json::json!({
+            "success": false,
+            "error": self.to_string(),
+            "code": self.code(),
+            "request_id": request_id
+        });
+        if let ApiError::Validation(fields) = self {
+            body["fields"] = serde_json::json!(fields);
+        }
+        let mut response = HttpResponse::build(self.status_code());
+        if let ApiError::TooManyAttempts(seconds) = self {
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(body)
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+impl From<HashError> for ApiError {
+    fn from(e: HashError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<TokenError> for ApiError {
+    fn from(e: TokenError) -> Self {
+        ApiError::Unauthenticated(e)
+    }
+}
+
+impl From<RefreshError> for ApiError {
+    fn from(e: RefreshError) -> Self {
+        ApiError::InvalidRefreshToken(e)
+    }
+}
+
+impl From<VerificationError> for ApiError {
+    fn from(e: VerificationError) -> Self {
+        ApiError::InvalidVerificationToken(e)
+    }
+}
+
+impl From<ResetError> for ApiError {
+    fn from(e: ResetError) -> Self {
+        ApiError::InvalidResetToken(e)
+    }
+}
+
+impl From<TwoFactorError> for ApiError {
+    fn from(e: TwoFactorError) -> Self {
+        ApiError::TwoFactor(e)
+    }
+}
+
+impl From<OidcError> for ApiError {
+    fn from(e: OidcError) -> Self {
+        ApiError::Oidc(e)
+    }
+}
+
+// Malformed JSON bodies and path segments get the standard envelope instead of
+// actix's plain-text default.
+pub fn json_error(err: actix_web::error::JsonPayloadError, _req: &actix_web::HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid request body: {}", err)).into()
+}
+
+pub fn query_error(err: actix_web::error::QueryPayloadError, _req: &actix_web::HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid query string: {}", err)).into()
+}
+
+pub fn path_error(err: actix_web::error::PathError, _req: &actix_web::HttpRequest) -> actix_web::Error {
+    ApiError::BadRequest(format!("Invalid path parameter: {}", err)).into()
+}
+
+pub async fn route_not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound("Route not found"))
+}
+
+fn current_request_id() -> String {
+    REQUEST_ID.try_with(|id| id.clone()).unwrap_or_default()
+}
+
+// Tags each request with an id (the caller's X-Request-Id when it looks sane,
+// otherwise a fresh uuid), exposes it to error responses and echoes it back.
+pub async fn request_id(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+
+    let mut res = REQUEST_ID.scope(id.clone(), next.call(req)).await?;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::{env, fmt};
+
+use actix_web::web;
+use data_encoding::BASE64URL_NOPAD;
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::mail;
+
+// OpenID Connect sign-in through the authorization code flow with PKCE. Providers are listed
+// in OIDC_PROVIDERS (e.g. "google,microsoft") and each one is configured through
+// OIDC_<NAME>_ISSUER, OIDC_<NAME>_CLIENT_ID and OIDC_<NAME>_CLIENT_SECRET, plus optionally
+// OIDC_<NAME>_REDIRECT_URI (default: APP_URL/auth/callback/<name>) and OIDC_<NAME>_SCOPES.
+const DEFAULT_SCOPES: &str = "openid email profile";
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+#[derive(Debug)]
+pub enum OidcError {
+    Config(String),
+    InvalidState,
+    Exchange(String),
+    InvalidIdToken(String),
+    Unavailable(String),
+    UnverifiedEmail,
+}
+
+impl fmt::Display for OidcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OidcError::Config(e) => write!(f, "Invalid OIDC configuration: {}", e),
+            OidcError::InvalidState => write!(f, "Sign-in attempt is invalid or has expired, start again"),
+            OidcError::Exchange(e) => write!(f, "The sign-in provider rejected the authorization code: {}", e),
+            OidcError::InvalidIdToken(e) => write!(f, "The sign-in provider returned an invalid identity token: {}", e),
+            OidcError::Unavailable(e) => write!(f, "The sign-in provider could not be reached: {}", e),
+            OidcError::UnverifiedEmail => write!(f, "Your sign-in provider has not verified your email address"),
+        }
+    }
+}
+
+// Endpoints from the provider's discovery document.
+#[derive(Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+// The ID token claims this backend reads.
+#[derive(Deserialize)]
+pub struct IdentityClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    // Multi-tenant Microsoft issuers name the tenant in `tid`.
+    tid: Option<String>,
+    pub sub: String,
+    pub email: Option<String>,
+    // Most providers send a boolean, some a string.
+    email_verified: Option<serde_json::Value>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub name: Option<String>,
+}
+
+impl IdentityClaims {
+    // The email address, provided the provider vouches for it.
+    pub fn verified_email(&self) -> Option<&str> {
+        let verified = self.email_verified.as_ref().is_some_and(|v| v == true || v == "true");
+        self.email.as_deref().filter(|email| verified && !email.is_empty())
+    }
+}
+
+pub struct Provider {
+    pub name: String,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    scopes: String,
+    metadata: Mutex<Option<Arc<Metadata>>>,
+}
+
+pub struct Providers {
+    client: reqwest::Client,
+    providers: HashMap<String, Provider>,
+}
+
+pub type OidcProviders = web::Data<Providers>;
+
+fn provider_var(name: &str, key: &str) -> Option<String> {
+    env::var(format!("OIDC_{}_{}", name.to_uppercase(), key)).ok().filter(|value| !value.is_empty())
+}
+
+// The ID token is trusted because it comes straight from the token endpoint (OpenID Connect
+// Core 3.1.3.7), so that endpoint must be reached over TLS. Plain http is only accepted for
+// loopback issuers such as a local mock provider.
+fn check_issuer_url(issuer: &str) -> Result<(), OidcError> {
+    let url = Url::parse(issuer).map_err(|e| OidcError::Config(format!("invalid issuer {}: {}", issuer, e)))?;
+    let loopback = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1") | Some("[::1]"));
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(OidcError::Config(format!("issuer {} must use https", issuer))),
+    }
+}
+
+impl Providers {
+    pub fn from_env() -> Result<Self, OidcError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| OidcError::Config(e.to_string()))?;
+
+        let mut providers = HashMap::new();
+        let names = env::var("OIDC_PROVIDERS").unwrap_or_default();
+        for name in names.split(',').map(|name| name.trim().to_lowercase()).filter(|name| !name.is_empty()) {
+            let required = |key: &str| provider_var(&name, key)
+                .ok_or_else(|| OidcError::Config(format!("OIDC_{}_{} is not set", name.to_uppercase(), key)));
+            let issuer = required("ISSUER")?.trim_end_matches('/').to_string();
+            check_issuer_url(&issuer)?;
+            let provider = Provider {
+                issuer,
+                client_id: required("CLIENT_ID")?,
+                client_secret: required("CLIENT_SECRET")?,
+                redirect_uri: provider_var(&name, "REDIRECT_URI")
+                    .unwrap_or_else(|| mail::app_link(&format!("/auth/callback/{}", name))),
+                scopes: provider_var(&name, "SCOPES").unwrap_or_else(|| DEFAULT_SCOPES.to_string()),
+                metadata: Mutex::new(None),
+                name: name.clone(),
+            };
+            providers.insert(name, provider);
+        }
+        Ok(Providers { client, providers })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Provider> {
+        self.providers.get(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.providers.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    // Fetched on first use and kept for the life of the process.
+    async fn metadata(&self, provider: &Provider) -> Result<Arc<Metadata>, OidcError> {
+        if let Some(metadata) = provider.metadata.lock().unwrap_or_else(|e| e.into_inner()).clone() {
+            return Ok(metadata);
+        }
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: Metadata = self.client.get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| OidcError::Unavailable(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OidcError::Unavailable(format!("invalid discovery document: {}", e)))?;
+        let metadata = Arc::new(metadata);
+        *provider.metadata.lock().unwrap_or_else(|e| e.into_inner()) = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    pub async fn authorization_url(&self, provider: &Provider, state: &str, nonce: &str, code_verifier: &str) -> Result<String, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| OidcError::Unavailable(format!("invalid authorization endpoint: {}", e)))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_uri)
+            .append_pair("scope", &provider.scopes)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &pkce_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.to_string())
+    }
+
+    // Trades the authorization code for the user's identity.
+    pub async fn exchange(&self, provider: &Provider, code: &str, code_verifier: &str, nonce: &str) -> Result<IdentityClaims, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let response = self.client.post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &provider.redirect_uri),
+                ("client_id", &provider.client_id),
+                ("client_secret", &provider.client_secret),
+                ("code_verifier", code_verifier),
+            ])
+            .send()
+            .await
+            .map_err(|e| OidcError::Unavailable(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body: String = response.text().await.unwrap_or_default().chars().take(200).collect();
+            // Only a generic message reaches the client, so keep the provider's answer here.
+            println!("[WARN] {} token endpoint answered {}: {}", provider.name, status, body);
+            return Err(OidcError::Exchange(format!("{} {}", status, body)));
+        }
+        let tokens: TokenResponse = response.json()
+            .await
+            .map_err(|e| OidcError::Exchange(format!("invalid token response: {}", e)))?;
+        let id_token = tokens.id_token.ok_or_else(|| OidcError::InvalidIdToken("no id_token in response".to_string()))?;
+
+        let claims = decode_claims(&id_token)?;
+        validate_claims(&claims, &metadata, &provider.client_id, nonce)?;
+        Ok(claims)
+    }
+}
+
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    BASE64URL_NOPAD.encode(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+fn decode_claims(id_token: &str) -> Result<IdentityClaims, OidcError> {
+    let payload = match id_token.split('.').collect::<Vec<_>>().as_slice() {
+        [_header, payload, _signature] => *payload,
+        _ => return Err(OidcError::InvalidIdToken("not a JWT".to_string())),
+    };
+    let payload = BASE64URL_NOPAD.decode(payload.trim_end_matches('=').as_bytes())
+        .map_err(|e| OidcError::InvalidIdToken(e.to_string()))?;
+    serde_json::from_slice(&payload).map_err(|e| OidcError::InvalidIdToken(e.to_string()))
+}
+
+fn validate_claims(claims: &IdentityClaims, metadata: &Metadata, client_id: &str, nonce: &str) -> Result<(), OidcError> {
+    let expected_issuer = match &claims.tid {
+        Some(tid) => metadata.issuer.replace("{tenantid}", tid),
+        None => metadata.issuer.clone(),
+    };
+    if claims.iss != expected_issuer {
+        return Err(OidcError::InvalidIdToken(format!("unexpected issuer {}", claims.iss)));
+    }
+    let audience_ok = match &claims.aud {
+        Audience::One(aud) => aud == client_id,
+        Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+    };
+    if !audience_ok {
+        return Err(OidcError::InvalidIdToken("token was issued to another client".to_string()));
+    }
+    if claims.exp + CLOCK_SKEW_SECONDS < chrono::Utc::now().timestamp() {
+        return Err(OidcError::InvalidIdToken("token has expired".to_string()));
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcError::InvalidIdToken("nonce does not match".to_string()));
+    }
+    if claims.sub.is_empty() {
+        return Err(OidcError::InvalidIdToken("missing subject".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(issuer: &str) -> Metadata {
+        Metadata {
+            issuer: issuer.to_string(),
+            authorization_endpoint: format!("{}/authorize", issuer),
+            token_endpoint: format!("{}/token", issuer),
+        }
+    }
+
+    fn claims(overrides: serde_json::Value) -> IdentityClaims {
+        let mut payload = serde_json::json!({
+            "iss": "https://id.example.com",
+            "aud": "client",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": "nonce",
+            "sub": "subject",
+            "email": "ada@example.com",
+            "email_verified": true
+        });
+        payload.as_object_mut().unwrap().extend(overrides.as_object().unwrap().clone());
+        let id_token = format!("{}.{}.", BASE64URL_NOPAD.encode(br#"{"alg":"none"}"#), BASE64URL_NOPAD.encode(payload.to_string().as_bytes()));
+        decode_claims(&id_token).unwrap()
+    }
+
+    fn check(overrides: serde_json::Value) -> Result<(), OidcError> {
+        validate_claims(&claims(overrides), &metadata("https://id.example.com"), "client", "nonce")
+    }
+
+    #[test]
+    fn accepts_matching_claims() {
+        assert!(check(serde_json::json!({})).is_ok());
+        assert!(check(serde_json::json!({ "aud": ["other", "client"] })).is_ok());
+        assert!(check(serde_json::json!({ "exp": chrono::Utc::now().timestamp() - CLOCK_SKEW_SECONDS + 5 })).is_ok());
+    }
+
+    #[test]
+    fn rejects_claims_meant_for_someone_else() {
+        for overrides in [
+            serde_json::json!({ "iss": "https://evil.example.com" }),
+            serde_json::json!({ "aud": "other" }),
+            serde_json::json!({ "aud": ["other"] }),
+            serde_json::json!({ "exp": chrono::Utc::now().timestamp() - CLOCK_SKEW_SECONDS - 5 }),
+            serde_json::json!({ "nonce": "replayed" }),
+            serde_json::json!({ "nonce": null }),
+            serde_json::json!({ "sub": "" }),
+        ] {
+            assert!(matches!(check(overrides.clone()), Err(OidcError::InvalidIdToken(_))), "{}", overrides);
+        }
+    }
+
+    #[test]
+    fn tenant_issuers_are_expanded() {
+        let metadata = metadata("https://login.example.com/{tenantid}/v2.0");
+        let tenant = claims(serde_json::json!({ "iss": "https://login.example.com/t1/v2.0", "tid": "t1" }));
+        assert!(validate_claims(&tenant, &metadata, "client", "nonce").is_ok());
+        let other = claims(serde_json::json!({ "iss": "https://login.example.com/t2/v2.0", "tid": "t1" }));
+        assert!(validate_claims(&other, &metadata, "client", "nonce").is_err());
+    }
+
+    #[test]
+    fn email_is_only_trusted_when_verified() {
+        assert_eq!(claims(serde_json::json!({})).verified_email(), Some("ada@example.com"));
+        assert_eq!(claims(serde_json::json!({ "email_verified": "true" })).verified_email(), Some("ada@example.com"));
+        assert_eq!(claims(serde_json::json!({ "email_verified": false })).verified_email(), None);
+        assert_eq!(claims(serde_json::json!({ "email_verified": null })).verified_email(), None);
+    }
+
+    #[test]
+    fn malformed_id_tokens_are_rejected() {
+        assert!(decode_claims("not-a-jwt").is_err());
+        assert!(decode_claims("a.!!!.c").is_err());
+        assert!(decode_claims(&format!("a.{}.c", BASE64URL_NOPAD.encode(b"{}"))).is_err());
+    }
+
+    #[test]
+    fn pkce_challenge_is_s256() {
+        assert_eq!(pkce_challenge("dBjftJeZ4CVP-mJ92K9qkVRr1nnd5-CmWnvYb0y3lvtc"), "iUiH3CEXzbJWiZ9hR72JA_x2UPEe2asBMAJjv0jY250");
+    }
+}
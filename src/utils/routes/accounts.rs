@@ -1,166 +1,369 @@
-use actix_web::{Responder, post, HttpResponse, get, web};
+use actix_web::{delete, post, put, HttpRequest, HttpResponse, get, web};
+use rusqlite::{Connection, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-
-use crate::users;
-
-#[derive(Deserialize, Serialize)]
+use validator::{Validate, ValidationError};
+
+use crate::enc;
+use crate::external_identities;
+use crate::login_events;
+use crate::onboarding;
+use crate::users::{self, AccountStatus, AccountType};
+use crate::mail::{self, Mail, Mailer};
+use crate::password_resets;
+use crate::privacy::Viewer;
+use crate::sessions::{self, ClientInfo, IssuedSession};
+use crate::throttle;
+use crate::two_factor;
+use crate::tokens;
+use crate::verification;
+use crate::auth::{check_status, client_info, AuthenticatedUser, EmployerUser, EnrollingUser, StudentUser};
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::{AccountRepo, PostRepo};
+use crate::repository::accounts::Account;
+use crate::validation::{FieldError, ValidatedJson};
+
+#[derive(Default, Deserialize, Serialize, Validate)]
+#[serde(default)]
 pub struct RegisterRequest {
+    #[validate(email, length(max = 255))]
     email: String,
+    #[validate(length(min = 8, max = 128))]
     password: String,
+    #[validate(length(min = 1, max = 100))]
     first_name: String,
+    #[validate(length(min = 1, max = 100))]
     last_name: String,
+    #[validate(custom(function = "self_service_account_type"))]
     account_type: String
 }
 
-#[derive(Deserialize)]
+// Administrators are never created through public registration.
+fn self_service_account_type(account_type: &str) -> Result<(), ValidationError> {
+    match AccountType::parse(account_type) {
+        Some(account_type) if account_type.is_self_service() => Ok(()),
+        _ => Err(ValidationError::new("one_of").with_message("must be one of: student, employer".into())),
+    }
+}
+
+#[derive(Deserialize, Validate)]
 pub struct UpdateEmployerAgreementsRequest {
-    employer_agreement: bool,
-    job_posting_guidelines: bool,
-    insurance_certificate: bool,
-    benefits_description: bool
+    #[validate(required)]
+    employer_agreement: Option<bool>,
+    #[validate(required)]
+    job_posting_guidelines: Option<bool>,
+    #[validate(required)]
+    insurance_certificate: Option<bool>,
+    #[validate(required)]
+    benefits_description: Option<bool>
 }
 
-#[get("/api/v1/total_users")]
-pub async fn get_total_users() -> impl Responder {
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
+#[derive(Deserialize, Validate)]
+pub struct UpdateStudentFormsRequest {
+    #[validate(required)]
+    resume: Option<bool>,
+    #[validate(required)]
+    transcript: Option<bool>,
+    #[validate(required)]
+    agreement: Option<bool>,
+    #[validate(required)]
+    background_check: Option<bool>
+}
 
-    let total: i64 = match conn.query_row(
-        "SELECT COUNT(*) FROM accounts",
-        [],
-        |row| row.get(0)
-    ) {
-        Ok(count) => count,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to count users: {}", e)
-        }))
-    };
+#[get("/api/v1/total_users")]
+pub async fn get_total_users(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let total = db::run(&pool, |conn| AccountRepo::new(conn).count(None)).await?;
 
-    HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "total_users": total
-    }))
+    })))
 }
 
 #[get("/api/v1/total_employers")]
-pub async fn get_total_employers() -> impl Responder {
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
+pub async fn get_total_employers(pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let total = db::run(&pool, |conn| AccountRepo::new(conn).count(Some(AccountType::Employer))).await?;
 
-    let total: i64 = match conn.query_row(
-        "SELECT COUNT(*) FROM accounts WHERE account_type = 'employer'",
-        [],
-        |row| row.get(0)
-    ) {
-        Ok(count) => count,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to count employers: {}", e)
-        }))
-    };
-
-    HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
         "total_employers": total
-    }))
+    })))
 }
 
 #[get("/api/v1/user")]
-pub async fn get_user(req: actix_web::HttpRequest) -> impl Responder {
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or(""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing Authorization header"
-        }))
-    };
+pub async fn get_user(auth: EnrollingUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let unique_id = auth.unique_id.clone();
+    let (user, posts) = db::run(&pool, move |conn| {
+        let user = AccountRepo::new(conn).find_by_unique_id(&unique_id)?;
+        let posts = PostRepo::new(conn).count_by_employer(&unique_id)?;
+        Ok((user, posts))
+    }).await?;
+    let user = user.ok_or(ApiError::NotFound("User not found"))?;
+
+    let onboarding = onboarding::checklist(auth.account_type, &onboarding::Progress {
+        profile: &user.profile,
+        email_verified: auth.status != AccountStatus::PendingVerification,
+        two_factor_enabled: auth.two_factor_enabled,
+        posts,
+    });
 
-    let uuid = auth_header.replace("Bearer ", "");
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "email": user.email,
+        "unique_id": user.unique_id,
+        "first_name": user.first_name,
+        "last_name": user.last_name,
+        "status": auth.status,
+        "created_at": user.created_at,
+        "last_login": user.last_login,
+        "two_factor": {
+            "enabled": auth.two_factor_enabled,
+            "setup_required": auth.two_factor_setup_required
+        },
+        "profile": user.profile,
+        "forms": user.profile.forms,
+        "tasks": {
+            auth.account_type.as_str(): onboarding.remaining()
+        },
+        "onboarding": onboarding
+    })))
+}
 
-    match users::NewUser::get_by_uuid(&uuid) {
-        Ok(user) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "email": user.email,
-            "unique_id": user.unique_id,
-            "first_name": user.first_name,
-            "last_name": user.last_name,
-            "profile": user.profile,
-            "forms": {
-                "student": {
-                    "resume": false,
-                    "transcript": false,
-                    "agreement": false,
-                    "background_check": false
-                },
-                "employer": {
-                    "employer_agreement": false,
-                    "job_posting_guidelines": false,
-                    "insurance_certificate": false,
-                    "benefits_description": false
-                }
-            },
-            "tasks": {
-                "student": [
-                    "Complete profile",
-                    "Upload resume",
-                    "Submit required forms"
-                ],
-                "employer": [
-                    "Complete company profile",
-                    "Submit required documentation",
-                    "Post job opportunities"
-                ]
-            }
-        })),
-        Err(e) => HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "error": format!("User not found: {}", e)
-        }))
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String
+}
+
+// Body shared by every endpoint that hands out credentials.
+fn session_response(unique_id: &str, account_type: AccountType, session: &IssuedSession) -> Result<HttpResponse, ApiError> {
+    let token = tokens::issue_access_token(unique_id, &session.id, account_type.as_str())?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "uuid": unique_id,
+        "account_type": account_type,
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": tokens::ACCESS_TOKEN_TTL_SECONDS,
+        "refresh_token": session.refresh_token,
+        "refresh_expires_in": sessions::REFRESH_TOKEN_TTL_SECONDS
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    token: String
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1))]
+    token: String,
+    #[validate(length(min = 8, max = 128))]
+    password: String
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct ChangePasswordRequest {
+    // Left empty by accounts without a password (see `check_current_password`).
+    #[validate(length(max = 128))]
+    current_password: String,
+    #[validate(length(min = 8, max = 128))]
+    new_password: String
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct ChangeEmailRequest {
+    #[validate(email, length(max = 255))]
+    email: String,
+    // Left empty by accounts without a password (see `check_current_password`).
+    #[validate(length(max = 128))]
+    current_password: String
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct ConfirmPasswordRequest {
+    // Left empty by accounts without a password (see `check_current_password`).
+    #[validate(length(max = 128))]
+    current_password: String
+}
+
+// Reported like any other field error so forms can show it next to the input.
+fn incorrect_current_password() -> ApiError {
+    ApiError::Validation(vec![FieldError {
+        field: "current_password".to_string(),
+        code: "incorrect".to_string(),
+        message: "is incorrect".to_string(),
+    }])
+}
+
+// Loads the caller's account, provided `password` is its current password. Accounts created
+// through a sign-in provider have no password; they confirm by signing in with a linked
+// provider again from this session shortly before (see `oidc_routes::identity_callback`).
+pub async fn check_current_password(pool: &DbPool, user: &AuthenticatedUser, password: String) -> Result<Account, ApiError> {
+    let (unique_id, session_id) = (user.unique_id.clone(), user.session_id.clone());
+    let account = db::run(pool, move |conn| {
+        let account = match AccountRepo::new(conn).find_by_unique_id(&unique_id)? {
+            Some(account) => account,
+            None => return Ok(Err(ApiError::NotFound("User not found"))),
+        };
+        if account.password.is_empty() && !external_identities::take_reauthentication(conn, &unique_id, &session_id)? {
+            return Ok(Err(ApiError::Validation(vec![FieldError {
+                field: "current_password".to_string(),
+                code: "reauthentication_required".to_string(),
+                message: "this account has no password, sign in with your provider again to confirm".to_string(),
+            }])));
+        }
+        Ok(Ok(account))
+    }).await??;
+    if account.password.is_empty() {
+        return Ok(account);
+    }
+    match enc::verify(password, account.password.clone()).await? {
+        true => Ok(account),
+        false => Err(incorrect_current_password()),
     }
 }
 
+async fn send_verification_email(mailer: &Mailer, email: String, token: &str) -> Result<(), mail::MailError> {
+    let link = mail::app_link(&format!("/verify?token={}", token));
+    mail::deliver(mailer, Mail {
+        to: email,
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Confirm this email address for your LeafDevs Jobs account by opening the link below:\n\n{}\n\nThe link expires in 24 hours.",
+            link
+        ),
+    }).await
+}
+
 #[post("/api/v1/register")]
-pub async fn register_account(req_body: String) -> impl Responder {
-    match serde_json::from_str::<RegisterRequest>(&req_body) {
-        Ok(register_request) => {
-            let new_user = users::NewUser::new(
-                register_request.email,
-                register_request.password,
-                register_request.first_name,
-                register_request.last_name,
-                register_request.account_type.clone()
-            );
-            if let Err(e) = users::NewUser::dump(&new_user) {
-                return HttpResponse::Ok().json(serde_json::json!({
-                    "success": false,
-                    "error": format!("Failed to create user: {}", e)
-                }));
-            }
-            let uuid = new_user.unique_id;
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "uuid": uuid,
-                "account_type": register_request.account_type
-            }))
-        },
-        Err(e) => {
-            HttpResponse::BadRequest().json(serde_json::json!({
-                "success": false,
-                "error": format!("Invalid request format: {}", e)
-            }))
-        }
+pub async fn register_account(req: HttpRequest, req_body: ValidatedJson<RegisterRequest>, pool: web::Data<DbPool>, mailer: Mailer) -> Result<HttpResponse, ApiError> {
+    let register_request = req_body.into_inner();
+    let client = client_info(&req);
+    let account_type = AccountType::parse(&register_request.account_type)
+        .filter(|account_type| account_type.is_self_service())
+        .ok_or_else(|| ApiError::BadRequest("Invalid account type".to_string()))?;
+
+    let password_hash = enc::hash(register_request.password).await?;
+    let created = db::run(&pool, move |conn| {
+        let new_user = users::NewUser::new(
+            register_request.email,
+            password_hash,
+            register_request.first_name,
+            register_request.last_name,
+            account_type
+        );
+        let tx = conn.transaction()?;
+        AccountRepo::new(&tx).insert(&new_user)?;
+        let verification_token = verification::issue(&tx, &new_user.unique_id, &new_user.email)?;
+        let refresh_token = start_session(&tx, &new_user.unique_id, new_user.status, &client)?;
+        tx.commit()?;
+        Ok((new_user.unique_id, new_user.account_type, new_user.email, verification_token, refresh_token))
+    }).await;
+
+    let (unique_id, account_type, email, verification_token, refresh_token) = match created {
+        Ok(created) => created,
+        Err(e) if e.is_unique_violation() => return Err(ApiError::Conflict("An account with this email already exists")),
+        Err(e) => return Err(e.into())
+    };
+
+    // The account exists either way; if the mail doesn't go out the user can ask for it again.
+    if let Err(e) = send_verification_email(&mailer, email, &verification_token).await {
+        println!("[ERROR] Could not send verification email to account {}: {}", unique_id, e);
+    }
+
+    session_response(&unique_id, account_type, &refresh_token)
+}
+
+#[post("/api/v1/auth/verify")]
+pub async fn verify_email(req_body: web::Json<VerifyEmailRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let token = req_body.into_inner().token;
+    let verified = db::run(&pool, move |conn| Ok(verification::consume(conn, &token))).await??;
+    println!("[LOG] Verified email address for account {}", verified.account_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "email": verified.email,
+        "message": "Email address verified"
+    })))
+}
+
+#[post("/api/v1/auth/verify/resend")]
+pub async fn resend_verification(user: AuthenticatedUser, pool: web::Data<DbPool>, mailer: Mailer) -> Result<HttpResponse, ApiError> {
+    if user.status != AccountStatus::PendingVerification {
+        return Err(ApiError::Conflict("Email address is already verified"));
+    }
+
+    let unique_id = user.unique_id.clone();
+    let (email, token) = db::run(&pool, move |conn| {
+        let email = match AccountRepo::new(conn).find_by_unique_id(&unique_id)? {
+            Some(account) => account.email,
+            None => return Ok(None)
+        };
+        let token = verification::issue(conn, &unique_id, &email)?;
+        Ok(Some((email, token)))
+    }).await?.ok_or(ApiError::NotFound("User not found"))?;
+
+    send_verification_email(&mailer, email, &token).await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Verification email sent"
+    })))
+}
+
+// Every sign-in ends here: it is recorded in the login history, and signing in again is how
+// owners undo `deactivate_account`.
+fn start_session(conn: &Connection, unique_id: &str, status: AccountStatus, client: &ClientInfo) -> rusqlite::Result<IssuedSession> {
+    let accounts = AccountRepo::new(conn);
+    if status == AccountStatus::Inactive {
+        accounts.reactivate(unique_id)?;
+        println!("[LOG] Reactivated account {} on sign in", unique_id);
     }
+    accounts.record_login(unique_id)?;
+    login_events::record(conn, unique_id, client)?;
+    sessions::issue(conn, unique_id, client)
+}
+
+pub enum LoginStep {
+    Session(IssuedSession),
+    Challenge(String),
+}
+
+// Accounts with two-factor authentication get a challenge to answer first, everyone else a session.
+pub fn finish_sign_in(conn: &Connection, unique_id: &str, status: AccountStatus, client: &ClientInfo) -> rusqlite::Result<LoginStep> {
+    match two_factor::is_enabled(conn, unique_id)? {
+        true => Ok(LoginStep::Challenge(two_factor::issue_challenge(conn, unique_id)?)),
+        false => Ok(LoginStep::Session(start_session(conn, unique_id, status, client)?)),
+    }
+}
+
+pub fn login_response(step: LoginStep, unique_id: &str, account_type: AccountType) -> Result<HttpResponse, ApiError> {
+    match step {
+        LoginStep::Session(session) => session_response(unique_id, account_type, &session),
+        LoginStep::Challenge(challenge) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "two_factor_required": true,
+            "challenge": challenge,
+            "expires_in": two_factor::CHALLENGE_TTL_SECONDS
+        })))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorLoginRequest {
+    challenge: String,
+    code: String
 }
 
 #[derive(Deserialize, Serialize)]
@@ -169,198 +372,416 @@ pub struct LoginRequest {
     password: String,
 }
 
+// Credentials are never logged; failures are only traced by account id, or not at all
+// when the email doesn't match an account.
 #[post("/api/v1/auth")]
-pub async fn login_account(req_body: String) -> impl Responder {
-    println!("[LOG] Login request received: {}", req_body);
-
-    let login_request: LoginRequest = match serde_json::from_str(&req_body) {
-        Ok(request) => request,
+pub async fn login_account(req: HttpRequest, req_body: web::Json<LoginRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let login_request = req_body.into_inner();
+    let client = client_info(&req);
+
+    // The attempt is counted before the password is checked; see `throttle`.
+    let email = login_request.email.clone();
+    let ip = client.ip.clone();
+    let user = db::run(&pool, move |conn| {
+        if let Some(seconds) = throttle::reserve_attempt(conn, &email, &ip)? {
+            return Ok(Err(ApiError::TooManyAttempts(seconds)));
+        }
+        Ok(Ok(AccountRepo::new(conn).find_by_email(&email)?))
+    }).await??;
+
+    // Accounts created through a sign-in provider have no password until they set one with a
+    // password reset. They and unknown emails are checked against a stand-in hash, so the
+    // response time doesn't tell them apart from a wrong password.
+    let verified = match user.as_ref().filter(|user| !user.password.is_empty()) {
+        Some(user) => enc::verify(login_request.password.clone(), user.password.clone()).await,
+        None => enc::verify_dummy(login_request.password.clone()).await,
+    };
+    let verified = match verified {
+        Ok(verified) => verified,
         Err(e) => {
-            println!("[ERROR] Failed to parse login request: {}", e);
-            return HttpResponse::Ok().json(serde_json::json!({
-                "success": false,
-                "error": "Invalid request format"
-            }));
+            println!("[ERROR] Password verification error: {}", e);
+            false
         }
     };
+    let user = match user {
+        Some(user) if verified => user,
+        user => {
+            if let Some(user) = user {
+                println!("[LOG] Password verification failed for user: {}", user.unique_id);
+            }
+            let (email, ip) = (login_request.email, client.ip);
+            db::run(&pool, move |conn| throttle::record_failure(conn, &email, &ip)).await?;
+            return Err(ApiError::InvalidCredentials);
+        },
+    };
+    // Only told to someone who knows the password.
+    if user.status == AccountStatus::Suspended {
+        let (email, ip) = (login_request.email, client.ip);
+        db::run(&pool, move |conn| throttle::release(conn, &email, &ip)).await?;
+        return Err(ApiError::AccountSuspended(user.status_reason));
+    }
 
-    println!("[LOG] Attempting to find user with email: {}", login_request.email);
-
-    let user = match users::NewUser::get_by_email(&login_request.email) {
-        Ok(user) => {
-            println!("[LOG] User query successful");
-            user
+    // Upgrade hashes made with outdated parameters while the plaintext is at hand.
+    let rehashed = match enc::needs_rehash(&user.password) {
+        Ok(true) => match enc::hash(login_request.password).await {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                println!("[ERROR] Could not rehash password for user {}: {}", user.unique_id, e);
+                None
+            }
         },
+        Ok(false) => None,
         Err(e) => {
-            println!("[ERROR] Database error while fetching user: {}", e);
-            return HttpResponse::Ok().json(serde_json::json!({
-                "success": false,
-                "error": "Database error"
-            }))
+            println!("[ERROR] Could not check password hash for user {}: {}", user.unique_id, e);
+            None
         }
     };
 
-    if let Some(user) = user {
-        println!("[LOG] User found, verifying password...");
-        match crate::enc::verify_password(&login_request.password, &user.password) {
-            Ok(true) => {
-                println!("[LOG] Password verification successful for user: {}", user.unique_id);
-                return HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "uuid": user.unique_id,
-                    "account_type": user.account_type
-                }));
-            },
-            Ok(false) => {
-                println!("[LOG] Password verification failed for user: {}", user.unique_id);
-            },
-            Err(e) => {
-                println!("[ERROR] Password verification error: {}", e);
-            }
+    let unique_id = user.unique_id.clone();
+    let status = user.status;
+    let email = login_request.email;
+    let step = db::run(&pool, move |conn| {
+        if let Some(hash) = rehashed {
+            AccountRepo::new(conn).update_password(&unique_id, &hash)?;
+            println!("[LOG] Upgraded password hash for user: {}", unique_id);
         }
-    } else {
-        println!("[LOG] No user found with email: {}", login_request.email);
-    }
+        let step = finish_sign_in(conn, &unique_id, status, &client)?;
+        match step {
+            LoginStep::Session(_) => throttle::record_success(conn, &email, &client.ip)?,
+            // The email's earlier failures stay counted until the second factor is in, and
+            // every code is reserved against them like a password.
+            LoginStep::Challenge(_) => throttle::release(conn, &email, &client.ip)?,
+        }
+        Ok(step)
+    }).await?;
 
-    println!("[LOG] Login attempt failed for email: {}", login_request.email);
-    HttpResponse::Ok().json(serde_json::json!({
-        "success": false,
-        "error": "Invalid email or password"
-    }))
+    login_response(step, &user.unique_id, user.account_type)
+}
+
+// Second login step for accounts with 2FA: trades the challenge from `login_account` and a
+// code from the authenticator app (or a recovery code) for a session.
+#[post("/api/v1/auth/2fa")]
+pub async fn complete_two_factor_login(req: HttpRequest, req_body: web::Json<TwoFactorLoginRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let TwoFactorLoginRequest { challenge, code } = req_body.into_inner();
+    let client = client_info(&req);
+    let (unique_id, account_type, session) = db::run(&pool, move |conn| {
+        let account = match two_factor::challenge_account(conn, &challenge)? {
+            Some(account_id) => AccountRepo::new(conn).find_by_unique_id(&account_id)?,
+            None => None,
+        };
+        let account = match account {
+            Some(account) => account,
+            None => return Ok(Err(ApiError::TwoFactor(two_factor::TwoFactorError::InvalidChallenge))),
+        };
+        // Codes are guessed against the same allowance as passwords.
+        if let Some(seconds) = throttle::reserve_attempt(conn, &account.email, &client.ip)? {
+            return Ok(Err(ApiError::TooManyAttempts(seconds)));
+        }
+        if let Err(e) = two_factor::complete_challenge(conn, &challenge, &code) {
+            throttle::record_failure(conn, &account.email, &client.ip)?;
+            return Ok(Err(e.into()));
+        }
+        throttle::record_success(conn, &account.email, &client.ip)?;
+        // An administrator may have suspended the account since the password step.
+        if account.status == AccountStatus::Suspended {
+            return Ok(Err(ApiError::AccountSuspended(account.status_reason)));
+        }
+        let session = start_session(conn, &account.unique_id, account.status, &client)?;
+        Ok(Ok((account.unique_id, account.account_type, session)))
+    }).await??;
+
+    session_response(&unique_id, account_type, &session)
+}
+
+#[post("/api/v1/auth/refresh")]
+pub async fn refresh_session(req: HttpRequest, req_body: web::Json<RefreshRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let refresh_token = req_body.into_inner().refresh_token;
+    let client = client_info(&req);
+    let rotated = db::run(&pool, move |conn| Ok(sessions::rotate(conn, &refresh_token, &client))).await??;
+
+    let account_id = rotated.account_id.clone();
+    let account = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_unique_id(&account_id))
+        .await?
+        .ok_or(ApiError::InvalidRefreshToken(sessions::RefreshError::Invalid))?;
+    check_status(&account)?;
+
+    session_response(&rotated.account_id, account.account_type, &rotated.session)
+}
+
+#[post("/api/v1/auth/logout")]
+pub async fn logout(req_body: web::Json<RefreshRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let refresh_token = req_body.into_inner().refresh_token;
+    db::run(&pool, move |conn| sessions::revoke(conn, &refresh_token)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Logged out"
+    })))
 }
 
 #[post("/api/v1/employer/agreements")]
 pub async fn update_employer_agreements(
-    req: actix_web::HttpRequest,
-    agreements: web::Json<UpdateEmployerAgreementsRequest>
-) -> impl Responder {
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
-    };
-
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
+    employer: EmployerUser,
+    agreements: ValidatedJson<UpdateEmployerAgreementsRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let unique_id = employer.unique_id.clone();
+    let mut profile = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_unique_id(&unique_id))
+        .await?
+        .ok_or(ApiError::NotFound("User not found"))?
+        .profile;
+
+    let forms = &mut profile.forms.employer;
+    forms.employer_agreement = agreements.employer_agreement.unwrap_or_default();
+    forms.job_posting_guidelines = agreements.job_posting_guidelines.unwrap_or_default();
+    forms.insurance_certificate = agreements.insurance_certificate.unwrap_or_default();
+    forms.benefits_description = agreements.benefits_description.unwrap_or_default();
+
+    let unique_id = employer.unique_id.clone();
+    db::run(&pool, move |conn| AccountRepo::new(conn).update_profile(&unique_id, &profile)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Employer agreements updated successfully"
+    })))
+}
 
-    let account_type: String = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?1",
-        [&auth_header],
-        |row| row.get(0)
-    ) {
-        Ok(t) => t,
-        Err(_) => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Invalid authorization token"
-        }))
-    };
+// The student counterpart of `update_employer_agreements`; these make up the last steps of
+// the student onboarding checklist.
+#[post("/api/v1/student/forms")]
+pub async fn update_student_forms(
+    student: StudentUser,
+    forms: ValidatedJson<UpdateStudentFormsRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let unique_id = student.unique_id.clone();
+    let mut profile = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_unique_id(&unique_id))
+        .await?
+        .ok_or(ApiError::NotFound("User not found"))?
+        .profile;
+
+    let stored = &mut profile.forms.student;
+    stored.resume = forms.resume.unwrap_or_default();
+    stored.transcript = forms.transcript.unwrap_or_default();
+    stored.agreement = forms.agreement.unwrap_or_default();
+    stored.background_check = forms.background_check.unwrap_or_default();
+
+    let unique_id = student.unique_id.clone();
+    db::run(&pool, move |conn| AccountRepo::new(conn).update_profile(&unique_id, &profile)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Student forms updated successfully"
+    })))
+}
 
-    if account_type != "employer" {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "Only employers can update employer agreements"
-        }));
+// Loads who is asking. Requests without a valid token are treated as anonymous.
+pub async fn load_viewer(pool: &DbPool, user: Option<AuthenticatedUser>) -> Result<Viewer, ApiError> {
+    match user {
+        Some(user) => {
+            let (id, is_admin) = (user.id, user.is_admin());
+            Ok(db::run(pool, move |conn| Viewer::for_account(conn, id, is_admin)).await?)
+        },
+        None => Ok(Viewer::anonymous()),
     }
+}
 
-    let current_profile: String = match conn.query_row(
-        "SELECT profile FROM accounts WHERE unique_id = ?1",
-        [&auth_header],
-        |row| row.get(0)
-    ) {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": "Failed to fetch current profile"
-        }))
-    };
-
-    let mut profile: serde_json::Value = match serde_json::from_str(&current_profile) {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": "Failed to parse profile data"
-        }))
-    };
+// Only the accounts the caller may see under each account's privacy settings.
+#[get("/api/v1/users")]
+pub async fn get_all_users_without_private_information_leaked(user: Option<AuthenticatedUser>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let viewer = load_viewer(&pool, user).await?;
+    let users: Vec<_> = db::run(&pool, |conn| AccountRepo::new(conn).list_public())
+        .await?
+        .into_iter()
+        .filter(|account| viewer.can_see(account.id, account.visibility, account.status))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "users": users
+    })))
+}
 
-    if let Some(forms) = profile.get_mut("forms") {
-        if let Some(employer) = forms.get_mut("employer") {
-            employer["employer_agreement"] = serde_json::json!(agreements.employer_agreement);
-            employer["job_posting_guidelines"] = serde_json::json!(agreements.job_posting_guidelines);
-            employer["insurance_certificate"] = serde_json::json!(agreements.insurance_certificate);
-            employer["benefits_description"] = serde_json::json!(agreements.benefits_description);
-        }
+// Accounts the caller may not see are reported as missing, so their existence isn't given away.
+#[get("/api/v1/users/{id}")]
+pub async fn get_public_profile(user: Option<AuthenticatedUser>, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let viewer = load_viewer(&pool, user).await?;
+    let account = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_id(id))
+        .await?
+        .filter(|account| viewer.can_see(account.id, account.profile.visibility(), account.status))
+        .ok_or(ApiError::NotFound("User not found"))?;
+
+    let privileged = viewer.is_privileged(account.id);
+    let mut user = serde_json::json!({
+        "id": account.id,
+        "first_name": account.first_name,
+        "last_name": account.last_name,
+        "account_type": account.account_type,
+        "profile": account.profile.public_view()
+    });
+    if privileged || account.profile.shows_email() {
+        user["email"] = serde_json::json!(account.email);
+    }
+    if privileged || account.profile.shows_phone() {
+        user["phone"] = serde_json::json!(account.profile.phone());
     }
 
-    match conn.execute(
-        "UPDATE accounts SET profile = ?1 WHERE unique_id = ?2",
-        [&profile.to_string(), &auth_header]
-    ) {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": "Employer agreements updated successfully"
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to update agreements: {}", e)
-        }))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "user": user
+    })))
+}
+
+// Always answers the same way, whether or not the email belongs to an account. The mail is
+// sent in the background so the response time doesn't give that away either.
+#[post("/api/v1/auth/forgot")]
+pub async fn forgot_password(req_body: web::Json<ForgotPasswordRequest>, pool: web::Data<DbPool>, mailer: Mailer) -> Result<HttpResponse, ApiError> {
+    let email = req_body.into_inner().email;
+    let issued = db::run(&pool, move |conn| {
+        let account = match AccountRepo::new(conn).find_by_email(&email)? {
+            Some(account) => account,
+            None => return Ok(None)
+        };
+        let token = password_resets::issue(conn, &account.unique_id)?;
+        Ok(Some((account.unique_id, account.email, token)))
+    }).await?;
+
+    if let Some((unique_id, email, token)) = issued {
+        actix_web::rt::spawn(async move {
+            let link = mail::app_link(&format!("/reset-password?token={}", token));
+            let sent = mail::deliver(&mailer, Mail {
+                to: email,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Someone asked to reset the password for your LeafDevs Jobs account.\n\n\
+                     Choose a new password by opening the link below:\n\n{}\n\n\
+                     The link expires in 1 hour. If this wasn't you, you can ignore this email.",
+                    link
+                ),
+            }).await;
+            if let Err(e) = sent {
+                println!("[ERROR] Could not send password reset email to account {}: {}", unique_id, e);
+            }
+        });
     }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "If an account exists for that email, a reset link is on its way"
+    })))
 }
 
-#[get("/api/v1/users")]
-pub async fn get_all_users_without_private_information_leaked() -> impl Responder {
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
+#[post("/api/v1/auth/reset")]
+pub async fn reset_password(req_body: ValidatedJson<ResetPasswordRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let ResetPasswordRequest { token, password } = req_body.into_inner();
+    let password_hash = enc::hash(password).await?;
+    let account_id = db::run(&pool, move |conn| Ok(password_resets::consume(conn, &token, &password_hash))).await??;
+    println!("[LOG] Password reset for account {}, all sessions revoked", account_id);
 
-    let mut stmt = match conn.prepare(
-        "SELECT id, first_name, last_name, profile, account_type FROM accounts"
-    ) {
-        Ok(stmt) => stmt,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to prepare statement: {}", e)
-        }))
-    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Password updated, please sign in again"
+    })))
+}
 
-    let users_iter = stmt.query_map([], |row| {
-        let profile_str: String = row.get(3)?;
-        let profile: serde_json::Value = serde_json::from_str(&profile_str).unwrap_or_default();
-
-        // Extract profile picture from the profile JSON
-        let profile_picture = profile.get("pfp")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-
-        Ok(serde_json::json!({
-            "id": row.get::<_, i64>(0)?,
-            "first_name": row.get::<_, String>(1)?,
-            "last_name": row.get::<_, String>(2)?,
-            "pfp": profile_picture,
-            "account_type": row.get::<_, String>(4)?
-        }))
-    });
+// Changing the password signs out every other session; the caller gets a fresh one back.
+#[put("/api/v1/user/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    req_body: ValidatedJson<ChangePasswordRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let ChangePasswordRequest { current_password, new_password } = req_body.into_inner();
+    let account = check_current_password(&pool, &user, current_password).await?;
+    let password_hash = enc::hash(new_password).await?;
+    let client = client_info(&req);
+
+    let session = db::run(&pool, move |conn| {
+        let tx = conn.transaction()?;
+        AccountRepo::new(&tx).update_password(&account.unique_id, &password_hash)?;
+        sessions::revoke_all(&tx, &account.unique_id)?;
+        let session = sessions::issue(&tx, &account.unique_id, &client)?;
+        tx.commit()?;
+        Ok(session)
+    }).await?;
+
+    println!("[LOG] Password changed for account {}, other sessions revoked", user.unique_id);
+    session_response(&user.unique_id, user.account_type, &session)
+}
 
-    let users: Vec<serde_json::Value> = match users_iter {
-        Ok(iter) => iter.filter_map(Result::ok).collect(),
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to fetch users: {}", e)
-        }))
-    };
+// The address only changes once the link sent to it is opened (see `verify_email`).
+#[put("/api/v1/user/email")]
+pub async fn change_email(
+    user: AuthenticatedUser,
+    req_body: ValidatedJson<ChangeEmailRequest>,
+    pool: web::Data<DbPool>,
+    mailer: Mailer
+) -> Result<HttpResponse, ApiError> {
+    let ChangeEmailRequest { email, current_password } = req_body.into_inner();
+    let account = check_current_password(&pool, &user, current_password).await?;
+    if account.email == email {
+        return Err(ApiError::Conflict("This is already your email address"));
+    }
+
+    let new_email = email.clone();
+    let token = db::run(&pool, move |conn| {
+        if AccountRepo::new(conn).find_by_email(&new_email)?.is_some() {
+            return Ok(None);
+        }
+        Ok(Some(verification::issue(conn, &account.unique_id, &new_email)?))
+    }).await?.ok_or(ApiError::Conflict("An account with this email already exists"))?;
+
+    send_verification_email(&mailer, email, &token).await
+        .map_err(|e| ApiError::Internal(e.to_string()))?;
 
-    HttpResponse::Ok().json(serde_json::json!({
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
         "success": true,
-        "users": users
-    }))
-}
\ No newline at end of file
+        "message": "Check your new email address for a confirmation link"
+    })))
+}
+
+// Signs the account out everywhere and keeps it out until its owner signs in again.
+// Nothing is deleted.
+#[post("/api/v1/user/deactivate")]
+pub async fn deactivate_account(user: AuthenticatedUser, req_body: ValidatedJson<ConfirmPasswordRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let account = check_current_password(&pool, &user, req_body.into_inner().current_password).await?;
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction()?;
+        AccountRepo::new(&tx).set_status(&account.unique_id, AccountStatus::Inactive, None)?;
+        sessions::revoke_all(&tx, &account.unique_id)?;
+        tx.commit()
+    }).await?;
+    println!("[LOG] Account {} deactivated by its owner", user.unique_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Account deactivated, sign in again to reactivate it"
+    })))
+}
+
+// Permanently deletes the caller's account and its posts, applications and messages
+// (see `AccountRepo::delete`). The last administrator has to stay.
+#[delete("/api/v1/user")]
+pub async fn delete_account(user: AuthenticatedUser, req_body: ValidatedJson<ConfirmPasswordRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    check_current_password(&pool, &user, req_body.into_inner().current_password).await?;
+
+    let unique_id = user.unique_id.clone();
+    let is_admin = user.is_admin();
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if is_admin && AccountRepo::new(&tx).count(Some(AccountType::Administrator))? <= 1 {
+            return Ok(Err(ApiError::Forbidden("The last administrator cannot delete their account")));
+        }
+        AccountRepo::new(&tx).delete(&unique_id)?;
+        tx.commit()?;
+        Ok(Ok(()))
+    }).await??;
+    println!("[LOG] Account {} deleted by its owner", user.unique_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Account deleted"
+    })))
+}
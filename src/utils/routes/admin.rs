@@ -0,0 +1,221 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use rusqlite::TransactionBehavior;
+use serde::Deserialize;
+use validator::{Validate, ValidationError};
+
+use crate::auth::AdminUser;
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::AccountRepo;
+use crate::sessions;
+use crate::throttle;
+use crate::two_factor;
+use crate::users::{AccountStatus, AccountType};
+use crate::validation::ValidatedJson;
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct UpdateRoleRequest {
+    #[validate(custom(function = "known_account_type"))]
+    account_type: String
+}
+
+fn known_account_type(account_type: &str) -> Result<(), ValidationError> {
+    match AccountType::parse(account_type) {
+        Some(_) => Ok(()),
+        None => Err(ValidationError::new("one_of").with_message("must be one of: student, employer, administrator".into())),
+    }
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct TwoFactorPolicyRequest {
+    #[validate(custom(function = "known_account_type"))]
+    account_type: String,
+    #[validate(required)]
+    required: Option<bool>
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct SuspendRequest {
+    #[validate(length(min = 1, max = 500))]
+    reason: String
+}
+
+// Promote or demote an account. Administrators cannot change their own role, so there is always
+// at least one administrator left who can undo a mistake.
+#[put("/api/v1/admin/users/{id}/role")]
+pub async fn update_user_role(
+    admin: AdminUser,
+    path: web::Path<i64>,
+    req_body: ValidatedJson<UpdateRoleRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let account_type = AccountType::parse(&req_body.account_type)
+        .ok_or_else(|| ApiError::BadRequest("Invalid account type".to_string()))?;
+
+    if id == admin.id {
+        return Err(ApiError::Forbidden("Administrators cannot change their own role"));
+    }
+
+    let previous = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let previous = AccountRepo::new(&tx).find_by_id(id)?.map(|account| account.account_type);
+        if previous.is_some() {
+            AccountRepo::new(&tx).set_account_type(id, account_type)?;
+        }
+        tx.commit()?;
+        Ok(previous)
+    }).await?.ok_or(ApiError::NotFound("User not found"))?;
+
+    println!(
+        "[LOG] Administrator {} changed account {} from {} to {}",
+        admin.unique_id, id, previous.as_str(), account_type.as_str()
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "id": id,
+        "account_type": account_type
+    })))
+}
+
+// Signs the account out everywhere and turns it away until it is reinstated. The reason is
+// shown to its owner when they try to sign in.
+#[put("/api/v1/admin/users/{id}/suspend")]
+pub async fn suspend_user(
+    admin: AdminUser,
+    path: web::Path<i64>,
+    req_body: ValidatedJson<SuspendRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    if id == admin.id {
+        return Err(ApiError::Forbidden("Administrators cannot suspend themselves"));
+    }
+
+    let reason = req_body.into_inner().reason;
+    let logged_reason = reason.clone();
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let account = match AccountRepo::new(&tx).find_by_id(id)? {
+            Some(account) => account,
+            None => return Ok(None)
+        };
+        AccountRepo::new(&tx).set_status(&account.unique_id, AccountStatus::Suspended, Some(&reason))?;
+        sessions::revoke_all(&tx, &account.unique_id)?;
+        tx.commit()?;
+        Ok(Some(()))
+    }).await?.ok_or(ApiError::NotFound("User not found"))?;
+
+    println!("[LOG] Administrator {} suspended account {}: {}", admin.unique_id, id, logged_reason);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "id": id,
+        "status": AccountStatus::Suspended
+    })))
+}
+
+#[put("/api/v1/admin/users/{id}/reinstate")]
+pub async fn reinstate_user(admin: AdminUser, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let status = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let account = match AccountRepo::new(&tx).find_by_id(id)? {
+            Some(account) => account,
+            None => return Ok(Err(ApiError::NotFound("User not found")))
+        };
+        if account.status != AccountStatus::Suspended {
+            return Ok(Err(ApiError::Conflict("Account is not suspended")));
+        }
+        AccountRepo::new(&tx).reactivate(&account.unique_id)?;
+        let status = AccountRepo::new(&tx).find_by_id(id)?.map(|account| account.status);
+        tx.commit()?;
+        Ok(Ok(status))
+    }).await??;
+
+    println!("[LOG] Administrator {} reinstated account {}", admin.unique_id, id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "id": id,
+        "status": status
+    })))
+}
+
+// Permanently deletes another account and its posts, applications and messages.
+// Administrators delete their own account through `DELETE /api/v1/user`.
+#[delete("/api/v1/admin/users/{id}")]
+pub async fn delete_user(admin: AdminUser, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    if id == admin.id {
+        return Err(ApiError::Forbidden("Use DELETE /api/v1/user to delete your own account"));
+    }
+
+    let unique_id = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let unique_id = match AccountRepo::new(&tx).find_by_id(id)? {
+            Some(account) => account.unique_id,
+            None => return Ok(None)
+        };
+        AccountRepo::new(&tx).delete(&unique_id)?;
+        tx.commit()?;
+        Ok(Some(unique_id))
+    }).await?.ok_or(ApiError::NotFound("User not found"))?;
+
+    println!("[LOG] Administrator {} deleted account {} ({})", admin.unique_id, id, unique_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "id": id
+    })))
+}
+
+// The most recent login lockouts, newest first.
+#[get("/api/v1/admin/lockouts")]
+pub async fn get_lockouts(_admin: AdminUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let lockouts = db::run(&pool, |conn| throttle::recent_lockouts(conn, 100)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "lockouts": lockouts
+    })))
+}
+
+#[get("/api/v1/admin/2fa-policy")]
+pub async fn get_two_factor_policy(_admin: AdminUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let policies = db::run(&pool, |conn| two_factor::policies(conn)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "policies": policies
+    })))
+}
+
+// Accounts of a type that requires 2FA keep their sessions, but every endpoint except
+// enrollment answers 403 two_factor_setup_required until they enroll.
+#[put("/api/v1/admin/2fa-policy")]
+pub async fn update_two_factor_policy(
+    admin: AdminUser,
+    req_body: ValidatedJson<TwoFactorPolicyRequest>,
+    pool: web::Data<DbPool>
+) -> Result<HttpResponse, ApiError> {
+    let account_type = AccountType::parse(&req_body.account_type)
+        .ok_or_else(|| ApiError::BadRequest("Invalid account type".to_string()))?;
+    let required = req_body.required.unwrap_or_default();
+
+    db::run(&pool, move |conn| two_factor::set_required(conn, account_type, required)).await?;
+    println!(
+        "[LOG] Administrator {} set two-factor requirement for {} accounts to {}",
+        admin.unique_id, account_type.as_str(), required
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "account_type": account_type,
+        "required": required
+    })))
+}
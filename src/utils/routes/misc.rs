@@ -1,4 +1,4 @@
-use actix_web::{post, get, web, Error, HttpResponse};
+use actix_web::{post, get, web, HttpResponse};
 use actix_multipart::Multipart;
 use futures_util::StreamExt;
 use std::fs;
@@ -6,26 +6,26 @@ use std::io::Write;
 use std::path::Path;
 use chrono::Utc;
 
+use crate::errors::ApiError;
+
+// Where uploaded files are stored and served from.
+pub const UPLOADS_DIR: &str = "./src/uploads";
+
 #[post("/api/v1/upload")]
-async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
+async fn upload(mut payload: Multipart) -> Result<HttpResponse, ApiError> {
     println!("Starting file upload...");
     let mut new_filename = String::new();
 
     // Create uploads directory if it doesn't exist
-    let uploads_dir = "../uploads";
-    match fs::create_dir_all(uploads_dir) {
-        Ok(_) => println!("Uploads directory exists or was created at: {}", uploads_dir),
+    match fs::create_dir_all(UPLOADS_DIR) {
+        Ok(_) => println!("Uploads directory exists or was created at: {}", UPLOADS_DIR),
         Err(e) => {
-            println!("Error creating uploads directory: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": "Failed to create uploads directory"
-            })));
+            return Err(ApiError::Internal(format!("Error creating uploads directory: {}", e)));
         }
     }
 
     while let Some(field) = payload.next().await {
-        let mut field = field?;
+        let mut field = field.map_err(|e| ApiError::BadRequest(format!("Invalid upload: {}", e)))?;
         let content_disposition = field.content_disposition();
         let original_filename = content_disposition
             .get_filename()
@@ -45,7 +45,7 @@ async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
         new_filename = format!("{}.{}", uuid::Uuid::new_v4(), extension);
         println!("Generated new filename: {}", new_filename);
         
-        let filepath = format!("./src/uploads/{}", new_filename);
+        let filepath = format!("{}/{}", UPLOADS_DIR, new_filename);
         println!("Attempting to create file at absolute path: {}", filepath);
         
         match fs::File::create(&filepath) {
@@ -60,20 +60,12 @@ async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
                                     println!("Wrote chunk of {} bytes", data.len());
                                 },
                                 Err(e) => {
-                                    println!("Error writing chunk to file: {}", e);
-                                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                                        "success": false,
-                                        "error": "Failed to write file chunk"
-                                    })));
+                                    return Err(ApiError::Internal(format!("Error writing chunk to file: {}", e)));
                                 }
                             }
                         },
                         Err(e) => {
-                            println!("Error reading chunk from upload: {}", e);
-                            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                                "success": false,
-                                "error": "Failed to read upload chunk"
-                            })));
+                            return Err(ApiError::BadRequest(format!("Failed to read upload chunk: {}", e)));
                         }
                     }
                 }
@@ -87,11 +79,7 @@ async fn upload(mut payload: Multipart) -> Result<HttpResponse, Error> {
                 }
             },
             Err(e) => {
-                println!("Error creating file: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "success": false,
-                    "error": "Failed to create file"
-                })));
+                return Err(ApiError::Internal(format!("Error creating file: {}", e)));
             }
         }
     }
@@ -121,8 +109,8 @@ pub async fn get_server_time() -> HttpResponse {
 
 
 #[get("/uploads/{filename}")]
-pub async fn serve_file(filename: web::Path<String>) -> HttpResponse {
-    let filepath = format!("./src/uploads/{}", filename);
+pub async fn serve_file(filename: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let filepath = format!("{}/{}", UPLOADS_DIR, filename);
     
     match fs::read(&filepath) {
         Ok(file_content) => {
@@ -138,13 +126,10 @@ pub async fn serve_file(filename: web::Path<String>) -> HttpResponse {
                 _ => "application/octet-stream",
             };
 
-            HttpResponse::Ok()
+            Ok(HttpResponse::Ok()
                 .content_type(content_type)
-                .body(file_content)
+                .body(file_content))
         },
-        Err(_) => HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "error": "File not found"
-        }))
+        Err(_) => Err(ApiError::NotFound("File not found"))
     }
 }
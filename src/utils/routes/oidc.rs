@@ -0,0 +1,247 @@
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use rusqlite::TransactionBehavior;
+use serde::Deserialize;
+
+use crate::account_routes::{finish_sign_in, login_response};
+use crate::auth::{client_info, AuthenticatedUser};
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::external_identities::{self, LoginAttempt, Purpose};
+use crate::oidc::{IdentityClaims, OidcError, OidcProviders};
+use crate::repository::AccountRepo;
+use crate::sessions;
+use crate::tokens;
+use crate::users::{self, AccountStatus, AccountType};
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct StartOidcRequest {
+    // Only used when the sign-in creates an account; defaults to student.
+    account_type: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackRequest {
+    code: String,
+    state: String
+}
+
+#[derive(Deserialize)]
+pub struct StartIdentityRequest {
+    // "link" or "reauthenticate".
+    purpose: String
+}
+
+// First and last name from the provider, falling back to the email address.
+fn account_names(claims: &IdentityClaims, email: &str) -> (String, String) {
+    let clean = |name: &str| name.trim().chars().take(100).collect::<String>();
+    if let (Some(first), Some(last)) = (&claims.given_name, &claims.family_name) {
+        if !first.trim().is_empty() {
+            return (clean(first), clean(last));
+        }
+    }
+    if let Some(name) = claims.name.as_deref().filter(|name| !name.trim().is_empty()) {
+        let (first, last) = name.trim().split_once(' ').unwrap_or((name.trim(), ""));
+        return (clean(first), clean(last));
+    }
+    (clean(email.split('@').next().unwrap_or(email)), String::new())
+}
+
+#[get("/api/v1/auth/oidc")]
+pub async fn list_providers(providers: OidcProviders) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "providers": providers.names()
+    }))
+}
+
+// Returns the provider URL to send the user to. The provider sends them back to the app's
+// redirect URI with `code` and `state`, which the app hands to `oidc_callback`.
+#[post("/api/v1/auth/oidc/{provider}/start")]
+pub async fn start_oidc_login(path: web::Path<String>, req_body: web::Json<StartOidcRequest>, providers: OidcProviders, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let provider = providers.get(&name).ok_or(ApiError::NotFound("Sign-in provider not found"))?;
+    let account_type = match req_body.into_inner().account_type {
+        Some(account_type) => AccountType::parse(&account_type)
+            .filter(|account_type| account_type.is_self_service())
+            .ok_or_else(|| ApiError::BadRequest("Invalid account type".to_string()))?,
+        None => AccountType::Student,
+    };
+
+    let attempt = LoginAttempt {
+        provider: provider.name.clone(),
+        code_verifier: tokens::generate_opaque_token(),
+        nonce: tokens::generate_opaque_token(),
+        account_type,
+        purpose: Purpose::SignIn,
+        account_id: None,
+    };
+    start_attempt(&providers, &pool, attempt).await
+}
+
+async fn start_attempt(providers: &OidcProviders, pool: &DbPool, attempt: LoginAttempt) -> Result<HttpResponse, ApiError> {
+    let provider = providers.get(&attempt.provider).ok_or(ApiError::NotFound("Sign-in provider not found"))?;
+    let state = tokens::generate_opaque_token();
+    let authorization_url = providers.authorization_url(provider, &state, &attempt.nonce, &attempt.code_verifier).await?;
+
+    let stored_state = state.clone();
+    db::run(pool, move |conn| external_identities::begin_login(conn, &stored_state, &attempt)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "authorization_url": authorization_url,
+        "state": state,
+        "expires_in": external_identities::LOGIN_ATTEMPT_TTL_SECONDS
+    })))
+}
+
+// Signs in the account linked to the provider identity. An identity seen for the first time
+// gets a new account, unless its (provider-verified) email address already belongs to one:
+// providers are only trusted with addresses nobody has proven yet, so the owner of a verified
+// account has to sign in to it and link the provider with `start_identity_flow` instead.
+#[post("/api/v1/auth/oidc/{provider}/callback")]
+pub async fn oidc_callback(req: HttpRequest, path: web::Path<String>, req_body: web::Json<OidcCallbackRequest>, providers: OidcProviders, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let provider = providers.get(&name).ok_or(ApiError::NotFound("Sign-in provider not found"))?;
+    let OidcCallbackRequest { code, state } = req_body.into_inner();
+    let client = client_info(&req);
+
+    let provider_name = provider.name.clone();
+    let attempt = db::run(&pool, move |conn| external_identities::take_login(conn, &provider_name, &state, Purpose::SignIn, None))
+        .await?
+        .ok_or(OidcError::InvalidState)?;
+    let claims = providers.exchange(provider, &code, &attempt.code_verifier, &attempt.nonce).await?;
+
+    let provider_name = provider.name.clone();
+    let (unique_id, account_type, step) = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let accounts = AccountRepo::new(&tx);
+        let account = match external_identities::find_account(&tx, &provider_name, &claims.sub)? {
+            Some(account_id) => accounts.find_by_unique_id(&account_id)?,
+            None => {
+                let email = match claims.verified_email() {
+                    Some(email) => email.to_string(),
+                    None => return Ok(Err(OidcError::UnverifiedEmail.into())),
+                };
+                let account_id = match accounts.find_by_email(&email)? {
+                    Some(account) if account.email_verified_at.is_some() => {
+                        return Ok(Err(ApiError::Conflict("An account with this email already exists, sign in to it and link this provider from your account settings")));
+                    },
+                    // Whoever registered an unverified account with this address never proved
+                    // they own it, so they lose the password and any sessions they hold.
+                    Some(account) => {
+                        accounts.update_password(&account.unique_id, "")?;
+                        sessions::revoke_all(&tx, &account.unique_id)?;
+                        accounts.confirm_email(&account.unique_id, &email)?;
+                        println!("[LOG] Linked {} identity to unverified account {}", provider_name, account.unique_id);
+                        account.unique_id
+                    },
+                    None => {
+                        let (first_name, last_name) = account_names(&claims, &email);
+                        let new_user = users::NewUser::new(email.clone(), String::new(), first_name, last_name, attempt.account_type);
+                        accounts.insert(&new_user)?;
+                        accounts.confirm_email(&new_user.unique_id, &email)?;
+                        println!("[LOG] Created account {} through {} sign-in", new_user.unique_id, provider_name);
+                        new_user.unique_id
+                    }
+                };
+                external_identities::link(&tx, &account_id, &provider_name, &claims.sub, Some(&email))?;
+                accounts.find_by_unique_id(&account_id)?
+            }
+        };
+        let account = match account {
+            Some(account) => account,
+            None => return Ok(Err(ApiError::NotFound("User not found"))),
+        };
+        if account.status == AccountStatus::Suspended {
+            return Ok(Err(ApiError::AccountSuspended(account.status_reason)));
+        }
+
+        external_identities::record_login(&tx, &provider_name, &claims.sub)?;
+        let step = finish_sign_in(&tx, &account.unique_id, account.status, &client)?;
+        tx.commit()?;
+        Ok(Ok((account.unique_id, account.account_type, step)))
+    }).await??;
+
+    login_response(step, &unique_id, account_type)
+}
+
+// Sends a signed-in user to the provider to link it to their account (`link`), or to prove
+// it is still them before a sensitive change when the account has no password (`reauthenticate`).
+#[post("/api/v1/user/identities/{provider}/start")]
+pub async fn start_identity_flow(user: AuthenticatedUser, path: web::Path<String>, req_body: web::Json<StartIdentityRequest>, providers: OidcProviders, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let provider = providers.get(&name).ok_or(ApiError::NotFound("Sign-in provider not found"))?;
+    let purpose = Purpose::parse(&req_body.purpose)
+        .filter(|purpose| *purpose != Purpose::SignIn)
+        .ok_or_else(|| ApiError::BadRequest("Purpose must be link or reauthenticate".to_string()))?;
+
+    let attempt = LoginAttempt {
+        provider: provider.name.clone(),
+        code_verifier: tokens::generate_opaque_token(),
+        nonce: tokens::generate_opaque_token(),
+        account_type: user.account_type,
+        purpose,
+        account_id: Some(user.unique_id.clone()),
+    };
+    start_attempt(&providers, &pool, attempt).await
+}
+
+// Finishes `start_identity_flow`. Only the account that started it can finish it.
+#[post("/api/v1/user/identities/{provider}/callback")]
+pub async fn identity_callback(user: AuthenticatedUser, path: web::Path<String>, req_body: web::Json<OidcCallbackRequest>, providers: OidcProviders, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let provider = providers.get(&name).ok_or(ApiError::NotFound("Sign-in provider not found"))?;
+    let OidcCallbackRequest { code, state } = req_body.into_inner();
+
+    let (provider_name, account_id) = (provider.name.clone(), user.unique_id.clone());
+    let attempt = db::run(&pool, move |conn| {
+        for purpose in [Purpose::Link, Purpose::Reauthenticate] {
+            if let Some(attempt) = external_identities::take_login(conn, &provider_name, &state, purpose, Some(&account_id))? {
+                return Ok(Some(attempt));
+            }
+        }
+        Ok(None)
+    })
+        .await?
+        .ok_or(OidcError::InvalidState)?;
+    let claims = providers.exchange(provider, &code, &attempt.code_verifier, &attempt.nonce).await?;
+
+    let (provider_name, account_id, session_id) = (provider.name.clone(), user.unique_id.clone(), user.session_id.clone());
+    let purpose = attempt.purpose;
+    db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let linked_to = external_identities::find_account(&tx, &provider_name, &claims.sub)?;
+        match (purpose, linked_to) {
+            (_, Some(other)) if other != account_id => {
+                return Ok(Err(ApiError::Conflict("This sign-in is linked to another account")));
+            },
+            (Purpose::Link, Some(_)) => {},
+            (Purpose::Link, None) => {
+                external_identities::link(&tx, &account_id, &provider_name, &claims.sub, claims.verified_email())?;
+                println!("[LOG] Linked {} identity to account {}", provider_name, account_id);
+            },
+            (_, Some(_)) => {
+                external_identities::record_login(&tx, &provider_name, &claims.sub)?;
+                external_identities::record_reauthentication(&tx, &account_id, &session_id)?;
+            },
+            (_, None) => {
+                return Ok(Err(ApiError::Forbidden("This sign-in is not linked to your account")));
+            },
+        }
+        tx.commit()?;
+        Ok(Ok(()))
+    }).await??;
+
+    Ok(HttpResponse::Ok().json(match purpose {
+        Purpose::Link => serde_json::json!({
+            "success": true,
+            "message": format!("Linked {} to your account", name)
+        }),
+        _ => serde_json::json!({
+            "success": true,
+            "message": "Identity confirmed",
+            "expires_in": external_identities::REAUTHENTICATION_TTL_SECONDS
+        }),
+    }))
+}
@@ -1,664 +1,283 @@
-use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
-use rusqlite::Connection;
-use serde::{Deserialize, Serialize};
-use crate::posts::Post;
-
-#[derive(Deserialize, Serialize)]
-pub struct CreatePostRequest {
-    pub title: String,
-    pub description: String,
-    pub tags: String,
-    pub documents: String,
-    pub tips: String,
-    pub skills: String,
-    pub experience: String,
-    pub jobtype: String,
-    pub location: String,
-    pub date: String,
-    pub questions: Option<String>,
-    pub company_name: String,
-}
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::account_routes::load_viewer;
+use crate::auth::{client_info, AdminUser, AuthenticatedUser, EmployerUser};
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::{AccountRepo, ApplicationRepo, Cursor, NewPost, PostChanges, PostRepo, PostSearch, PostSort};
+use crate::tokens;
+use crate::validation::{FieldError, ValidatedJson, ValidatedQuery};
 
 #[post("/api/v1/create_post")]
-pub async fn create_post(req: HttpRequest, req_body: web::Json<CreatePostRequest>) -> impl Responder {
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
-    };
-
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
-
-    // Verify user is an employer
-    let account_type: String = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?1",
-        [&auth_header],
-        |row| row.get(0)
-    ) {
-        Ok(t) => t,
-        Err(_) => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Invalid authorization token"
-
-        }))
-    };
-
-    if account_type != "employer" {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "Only employers can create job posts"
-        }));
-    }
+pub async fn create_post(employer: EmployerUser, req_body: ValidatedJson<NewPost>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    employer.require_verified_email()?;
+    let employer_id = employer.unique_id.clone();
 
     // Get current profile data
-    let current_profile: String = match conn.query_row(
-        "SELECT profile FROM accounts WHERE unique_id = ?1",
-        [&auth_header],
-        |row| row.get(0)
-    ) {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": "Failed to fetch current profile"
-        }))
-    };
-
-    // Parse current profile
-    let profile: serde_json::Value = match serde_json::from_str(&current_profile) {
-        Ok(p) => p,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": "Failed to parse profile data"
-        }))
-    };
+    let unique_id = employer_id.clone();
+    let account = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_unique_id(&unique_id))
+        .await?
+        .ok_or(ApiError::NotFound("User not found"))?;
 
     // Check employer agreements
-    if let Some(forms) = profile.get("forms") {
-        if let Some(employer) = forms.get("employer") {
-            let has_agreement = employer.get("employer_agreement").and_then(|v| v.as_bool()).unwrap_or(false);
-            let has_guidelines = employer.get("job_posting_guidelines").and_then(|v| v.as_bool()).unwrap_or(false);
-            let has_insurance = employer.get("insurance_certificate").and_then(|v| v.as_bool()).unwrap_or(false);
-            let has_benefits = employer.get("benefits_description").and_then(|v| v.as_bool()).unwrap_or(false);
-
-            if !has_agreement || !has_guidelines || !has_insurance || !has_benefits {
-                return HttpResponse::BadRequest().json(serde_json::json!({
-                    "success": false,
-                    "error": "Please complete all required employer forms before posting jobs"
-                }));
-            }
-        }
+    if !account.profile.forms.employer.is_complete() {
+        return Err(ApiError::BadRequest("Please complete all required employer forms before posting jobs".to_string()));
     }
 
-    match conn.execute(
-        "INSERT INTO posts (title, description, tags, documents, tips, skills, experience, jobtype, location, date, questions, company_name, employer_id, status)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
-        rusqlite::params![
-            req_body.title,
-            req_body.description,
-            req_body.tags,
-            req_body.documents,
-            req_body.tips,
-            req_body.skills,
-            req_body.experience,
-            req_body.jobtype,
-            req_body.location,
-            req_body.date,
-            req_body.questions, 
-            req_body.company_name,
-            auth_header,
-            "Pending"
-        ],
-    ) {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": "Post created successfully"
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to create post: {}", e)
-        }))
-    }
+    let post = req_body.into_inner();
+    db::run(&pool, move |conn| PostRepo::new(conn).insert(&employer_id, &post)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Post created successfully"
+    })))
 }
 
+// Accepted posts, filtered and searched, e.g. /api/v1/posts?q=rust&location=boston&tags=remote,internship.
+// Follow `next_cursor` (passed back as `cursor` with the same filters) for the next page.
 #[get("/api/v1/posts")]
-pub async fn get_posts() -> impl Responder {
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
-
-    let mut stmt = match conn.prepare(
-        "SELECT * FROM posts WHERE status = 'Accepted' ORDER BY date DESC"
-    ) {
-        Ok(stmt) => stmt,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
+pub async fn get_posts(query: ValidatedQuery<PostSearch>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let search = query.into_inner();
+    let sort = search.sort();
+    if sort == PostSort::Relevance && search.text_query().is_none() {
+        return Err(ApiError::Validation(vec![FieldError {
+            field: "sort".to_string(),
+            code: "needs_query".to_string(),
+            message: "relevance needs a search query (q)".to_string(),
+        }]));
+    }
+    let after = match search.cursor.as_deref() {
+        Some(cursor) => match Cursor::decode(cursor).filter(|cursor| cursor.sort() == sort) {
+            Some(cursor) => Some(cursor),
+            None => return Err(ApiError::Validation(vec![FieldError {
+                field: "cursor".to_string(),
+                code: "invalid".to_string(),
+                message: "is not a cursor from this search".to_string(),
+            }])),
+        },
+        None => None,
     };
 
-    let posts = stmt.query_map([], |row| {
-        Ok(serde_json::json!({
-            "id": row.get::<_, i64>("id")?,
-            "title": row.get::<_, String>("title")?,
-            "description": row.get::<_, String>("description")?,
-            "tags": row.get::<_, String>("tags")?,
-            "documents": row.get::<_, String>("documents")?,
-            "tips": row.get::<_, String>("tips")?,
-            "skills": row.get::<_, String>("skills")?,
-            "experience": row.get::<_, String>("experience")?,
-            "jobtype": row.get::<_, String>("jobtype")?,
-            "location": row.get::<_, String>("location")?,
-            "date": row.get::<_, String>("date")?,
-            "questions": row.get::<_, String>("questions")?,
-            "company_name": row.get::<_, String>("company_name")?,
-        }))
-    });
-
-    match posts {
-        Ok(posts) => {
-            let posts: Result<Vec<_>, _> = posts.collect();
-            match posts {
-                Ok(posts) => HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "posts": posts
-                })),
-                Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-                    "success": false,
-                    "error": format!("Failed to process posts: {}", e)
-                }))
-            }
+    let limit = search.limit() as usize;
+    let mut page = db::run(&pool, move |conn| PostRepo::new(conn).search(&search, after.as_ref())).await?;
+    let next_cursor = match page.hits.len() > limit {
+        true => {
+            page.hits.truncate(limit);
+            page.hits.last().map(|hit| hit.cursor(sort).encode())
         },
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to fetch posts: {}", e)
-        }))
-    }
-}
-
-#[put("/api/v1/posts/{id}/accept")]
-pub async fn accept_post(req: HttpRequest) -> impl Responder {
-    let id = req.match_info().get("id").unwrap();
-    println!("Attempting to accept post with ID: {}", id);
-
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
+        false => None,
     };
 
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database connection failed: {}", e)
-        }))
-    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "posts": page.hits,
+        "total": page.total,
+        "sort": sort,
+        "next_cursor": next_cursor
+    })))
+}
 
-    // Verify user is admin
-    let is_admin: bool = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?",
-        [&auth_header],
-        |row| {
-            let account_type: String = row.get(0)?;
-            Ok(account_type == "administrator")
-        }
-    ) {
-        Ok(is_admin) => is_admin,
-        Err(_) => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Unauthorized access"
-        }))
-    };
+#[derive(Deserialize, Validate)]
+pub struct PopularQuery {
+    #[validate(range(min = 1, max = 100))]
+    limit: Option<i64>
+}
 
-    if !is_admin {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "Only administrators can accept/reject posts"
-        }));
-    }
+// Tags on accepted posts with how many posts use each, most used first.
+#[get("/api/v1/tags")]
+pub async fn get_popular_tags(query: ValidatedQuery<PopularQuery>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let limit = query.into_inner().limit.unwrap_or(20);
+    let tags = db::run(&pool, move |conn| PostRepo::new(conn).popular_tags(limit)).await?;
 
-    match conn.execute(
-        "UPDATE posts SET status = 'Accepted' WHERE id = ?",
-        [id]
-    ) {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "tags": tags
+    })))
 }
 
-#[put("/api/v1/posts/{id}/reject")]
-pub async fn reject_post(req: HttpRequest) -> impl Responder {
-    let id = req.match_info().get("id").unwrap();
-    println!("Attempting to reject post with ID: {}", id);
-
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
-    };
+#[get("/api/v1/skills")]
+pub async fn get_popular_skills(query: ValidatedQuery<PopularQuery>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let limit = query.into_inner().limit.unwrap_or(20);
+    let skills = db::run(&pool, move |conn| PostRepo::new(conn).popular_skills(limit)).await?;
 
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database connection failed: {}", e)
-        }))
-    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "skills": skills
+    })))
+}
 
-    // Verify user is admin
-    let is_admin: bool = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?",
-        [&auth_header],
-        |row| {
-            let account_type: String = row.get(0)?;
-            Ok(account_type == "administrator")
+// Whom a view is counted against. Anonymous visitors are told apart by address and user
+// agent, and only a digest of those is stored.
+fn viewer_key(req: &HttpRequest, user: Option<&AuthenticatedUser>) -> String {
+    match user {
+        Some(user) => format!("account:{}", user.unique_id),
+        None => {
+            let client = client_info(req);
+            let fingerprint = format!("{}\n{}", client.ip, client.user_agent.unwrap_or_default());
+            format!("anonymous:{}", tokens::hash_opaque_token(&fingerprint))
         }
-    ) {
-        Ok(is_admin) => is_admin,
-        Err(_) => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Unauthorized access"
-        }))
-    };
-
-    if !is_admin {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "Only administrators can reject posts"
-        }));
     }
+}
 
-    let result = match req.headers().get("action") {
-        Some(action) if action.to_str().unwrap_or("") == "reject" => {
-            conn.execute(
-                "UPDATE posts SET status = 'Rejected' WHERE id = ?",
-                [id]
-            )
-        },
-
-        _ => {
-            conn.execute(
-                "DELETE FROM posts WHERE id = ?",
-                [id]
-            )
+// One post with its employer. Posts that aren't accepted are only shown to their owner and
+// administrators, who also get the post's application and view counts. Views by anyone else
+// are recorded, once per viewer.
+#[get("/api/v1/posts/{id}")]
+pub async fn get_post(req: HttpRequest, user: Option<AuthenticatedUser>, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let key = viewer_key(&req, user.as_ref());
+    let user_id = user.as_ref().map(|user| user.unique_id.clone());
+    let is_admin = user.as_ref().is_some_and(|user| user.is_admin());
+    let viewer = load_viewer(&pool, user).await?;
+
+    let (post, employer, has_applied, stats) = db::run(&pool, move |conn| {
+        let post = match PostRepo::new(conn).find_by_id(id)? {
+            Some(post) => post,
+            None => return Ok(None)
+        };
+        let privileged = is_admin || user_id.as_deref() == Some(post.employer_id.as_str());
+        if post.status != "Accepted" && !privileged {
+            return Ok(None);
         }
-    };
-
-    match result {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    }
-}   
-
-
-
-#[get("/api/v1/pending_posts")]
-pub async fn get_pending_posts(req: HttpRequest) -> impl Responder {
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
-    };
-
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database connection failed: {}", e)
-        }))
-    };
-
-    // Verify user is admin
-    let is_admin: bool = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?",
-        [&auth_header],
-        |row| {
-            let account_type: String = row.get(0)?;
-            Ok(account_type == "administrator")
+        if !privileged {
+            PostRepo::new(conn).record_view(id, &key)?;
         }
-    ) {
-        Ok(is_admin) => is_admin,
-        Err(_) => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Unauthorized access"
-        }))
-    };
 
-    if !is_admin {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "Only administrators can view pending posts"
-        }));
+        let applications = ApplicationRepo::new(conn);
+        let has_applied = match &user_id {
+            Some(user_id) => applications.has_applied(id, user_id)?,
+            None => false
+        };
+        let stats = match privileged {
+            true => Some((applications.count_for_post(id)?, PostRepo::new(conn).view_count(id)?)),
+            false => None
+        };
+        let employer = AccountRepo::new(conn).find_by_unique_id(&post.employer_id)?;
+        Ok(Some((post, employer, has_applied, stats)))
+    })
+        .await?
+        .ok_or(ApiError::NotFound("Post not found"))?;
+
+    // The company name is part of the post; who posted it follows their privacy settings.
+    let mut employer_info = serde_json::json!({ "company_name": post.company_name });
+    if let Some(account) = employer.filter(|account| viewer.can_see(account.id, account.profile.visibility(), account.status)) {
+        employer_info["id"] = serde_json::json!(account.id);
+        employer_info["first_name"] = serde_json::json!(account.first_name);
+        employer_info["last_name"] = serde_json::json!(account.last_name);
+        employer_info["profile"] = serde_json::json!(account.profile.public_view());
     }
 
-    let mut stmt = match conn.prepare(
-        "SELECT * FROM posts WHERE status = 'Pending' ORDER BY date DESC"
-    ) {
-        Ok(stmt) => stmt,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database error: {}", e)
-        }))
-    };
-
-    let posts = stmt.query_map([], |row| {
-        Ok(serde_json::json!({
-            "id": row.get::<_, i64>("id")?,
-            "title": row.get::<_, String>("title")?,
-            "description": row.get::<_, String>("description")?,
-            "tags": row.get::<_, String>("tags")?,
-            "documents": row.get::<_, String>("documents")?,
-            "tips": row.get::<_, String>("tips")?,
-            "skills": row.get::<_, String>("skills")?,
-            "experience": row.get::<_, String>("experience")?,
-            "jobtype": row.get::<_, String>("jobtype")?,
-            "location": row.get::<_, String>("location")?,
-            "date": row.get::<_, String>("date")?,
-            "questions": row.get::<_, String>("questions")?,
-            "company_name": row.get::<_, String>("company_name")?,
-            "employer_id": row.get::<_, String>("employer_id")?
-        }))
+    let mut body = serde_json::json!({
+        "success": true,
+        "post": post,
+        "employer": employer_info,
+        "has_applied": has_applied
     });
+    if let Some((application_count, view_count)) = stats {
+        body["application_count"] = serde_json::json!(application_count);
+        body["view_count"] = serde_json::json!(view_count);
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
 
-    match posts {
-        Ok(posts) => {
-            let posts: Result<Vec<_>, _> = posts.collect();
-            match posts {
-                Ok(posts) => HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "posts": posts
-                })),
-                Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-                    "success": false,
-                    "error": format!("Failed to process posts: {}", e)
-                }))
-            }
-        },
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to fetch posts: {}", e)
-        }))
+#[put("/api/v1/posts/{id}/accept")]
+pub async fn accept_post(_admin: AdminUser, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let updated = db::run(&pool, move |conn| PostRepo::new(conn).set_status(id, "Accepted")).await?;
+    if updated == 0 {
+        return Err(ApiError::NotFound("Post not found"));
     }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true
+    })))
 }
 
+#[put("/api/v1/posts/{id}/reject")]
+pub async fn reject_post(_admin: AdminUser, req: HttpRequest, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let keep_rejected = matches!(req.headers().get("action").and_then(|a| a.to_str().ok()), Some("reject"));
+    let changed = db::run(&pool, move |conn| match keep_rejected {
+        true => PostRepo::new(conn).set_status(id, "Rejected"),
+        false => PostRepo::new(conn).delete(id)
+    }).await?;
+    if changed == 0 {
+        return Err(ApiError::NotFound("Post not found"));
+    }
 
-#[get("/api/v1/my_posts")]
-pub async fn get_my_posts(req: HttpRequest) -> impl Responder {
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "Missing authorization header"
-        }))
-    };
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database connection failed: {}", e)
-        }))
-    };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true
+    })))
+}
 
-    let mut stmt = match conn.prepare("SELECT * FROM posts WHERE employer_id = ?") {
-        Ok(stmt) => stmt,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false, 
-            "error": format!("Failed to prepare statement: {}", e)
-        }))
-    };
+#[get("/api/v1/pending_posts")]
+pub async fn get_pending_posts(_admin: AdminUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let posts = db::run(&pool, |conn| PostRepo::new(conn).list_by_status("Pending")).await?;
 
-    let posts = stmt.query_map([&auth_header], |row| {
-        Ok(serde_json::json!({
-            "id": row.get::<_, i64>("id")?,
-            "title": row.get::<_, String>("title")?,
-            "description": row.get::<_, String>("description")?,
-            "tags": row.get::<_, String>("tags")?,
-            "documents": row.get::<_, String>("documents")?,
-            "tips": row.get::<_, String>("tips")?,
-            "skills": row.get::<_, String>("skills")?,
-            "experience": row.get::<_, String>("experience")?,
-            "jobtype": row.get::<_, String>("jobtype")?,
-            "location": row.get::<_, String>("location")?,
-            "date": row.get::<_, String>("date")?,
-            "questions": row.get::<_, String>("questions")?,
-            "company_name": row.get::<_, String>("company_name")?
-        }))
-    });
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "posts": posts
+    })))
+}
 
-    match posts {
-        Ok(posts) => {
-            let posts: Result<Vec<_>, _> = posts.collect();
-            match posts {
-                Ok(posts) => HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "posts": posts
-                })),
-                Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-                    "success": false,
-                    "error": format!("Failed to process posts: {}", e)
-                }))
-            }
-        },
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to fetch posts: {}", e)
-        }))
-    }
-    
+#[get("/api/v1/my_posts")]
+pub async fn get_my_posts(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let unique_id = user.unique_id.clone();
+    let posts = db::run(&pool, move |conn| PostRepo::new(conn).list_by_employer(&unique_id)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "posts": posts
+    })))
 }
 
 #[delete("/api/v1/posts/{id}")]
-pub async fn delete_post(req: HttpRequest) -> impl Responder {
-    let id = req.match_info().get("id").unwrap();
+pub async fn delete_post(user: AuthenticatedUser, path: web::Path<i64>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
     println!("Attempting to delete post with ID: {}", id);
 
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => {
-            println!("Delete post failed: No authorization header provided");
-            return HttpResponse::Unauthorized().json(serde_json::json!({
-                "success": false,
-                "error": "No authorization header provided"
-            }))
-        }
-    };
-
-    let conn = match Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => {
-            println!("Delete post failed: Database connection error: {}", e);
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Database connection error: {}", e)
-            }))
-        }
-    };
-
-    // Check if user is admin
-    let account_type: String = match conn.query_row(
-        "SELECT account_type FROM accounts WHERE unique_id = ?1",
-        [&auth_header],
-        |row| row.get(0)
-    ) {
-        Ok(t) => t,
-        Err(e) => {
-            println!("Delete post failed: Could not verify account type: {}", e);
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Failed to verify account type: {}", e)
-            }))
-        }
-    };
-
     // Only verify ownership if not admin
-    if account_type != "administrator" {
+    if !user.is_admin() {
         // Verify the post belongs to this employer
-        let post_owner = match conn.query_row(
-            "SELECT employer_id FROM posts WHERE id = ?",
-            [id],
-            |row| row.get::<_, String>(0)
-        ) {
-            Ok(employer_id) => employer_id,
-            Err(e) => {
-                println!("Delete post failed: Could not verify post ownership: {}", e);
-                return HttpResponse::InternalServerError().json(serde_json::json!({
-                    "success": false, 
-                    "error": format!("Failed to verify post ownership: {}", e)
-                }))
-            }
-        };
+        let post_owner = db::run(&pool, move |conn| PostRepo::new(conn).employer_of(id))
+            .await?
+            .ok_or(ApiError::NotFound("Post not found"))?;
 
-        if post_owner != auth_header {
-            println!("Delete post failed: Unauthorized attempt to delete post {} by user {}", id, auth_header);
-            return HttpResponse::Forbidden().json(serde_json::json!({
-                "success": false,
-                "error": "You do not have permission to delete this post"
-            }));
+        if post_owner != user.unique_id {
+            println!("Delete post failed: Unauthorized attempt to delete post {} by user {}", id, user.unique_id);
+            return Err(ApiError::Forbidden("You do not have permission to delete this post"));
         }
     }
 
-    // First delete any related records in child tables
-    match conn.execute("DELETE FROM applications WHERE post_id = ?", [id]) {
-        Ok(_) => (),
-        Err(e) => {
-            println!("Delete post failed: Could not delete related applications: {}", e);
-            return HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Failed to delete related applications: {}", e)
-            }))
-        }
-    }
+    db::run(&pool, move |conn| PostRepo::new(conn).delete(id)).await?;
+    println!("Successfully deleted post with ID: {}", id);
 
-    // Then delete the post
-    match conn.execute("DELETE FROM posts WHERE id = ?", [id]) {
-        Ok(_) => {
-            println!("Successfully deleted post with ID: {}", id);
-            HttpResponse::Ok().json(serde_json::json!({
-                "success": true,
-                "message": format!("Post with ID {} deleted successfully", id)
-            }))
-        },
-        Err(e) => {
-            println!("Delete post failed: Database error while deleting post {}: {}", id, e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "success": false,
-                "error": format!("Failed to delete post: {}", e)
-            }))
-        }
-    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": format!("Post with ID {} deleted successfully", id)
+    })))
 }
 
 #[put("/api/v1/posts/{id}")]
-pub async fn update_post(req: HttpRequest, body: web::Json<Post>) -> impl Responder {
-    let id = match req.match_info().get("id") {
-        Some(id) => id,
-        None => return HttpResponse::BadRequest().json(serde_json::json!({
-            "success": false,
-            "error": "Missing post ID"
-        }))
-    };
-
-    let auth_header = match req.headers().get("Authorization") {
-        Some(header) => header.to_str().unwrap_or("").replace("Bearer ", ""),
-        None => return HttpResponse::Unauthorized().json(serde_json::json!({
-            "success": false,
-            "error": "No authorization header provided"
-        }))
-    };
-
-    let conn = match rusqlite::Connection::open("fbla.db") {
-        Ok(conn) => conn,
-        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Database connection failed: {}", e)
-        }))
-    };
+pub async fn update_post(user: AuthenticatedUser, path: web::Path<i64>, body: ValidatedJson<PostChanges>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
 
     // Verify post ownership
-    let post_owner: String = match conn.query_row(
-        "SELECT employer_id FROM posts WHERE id = ?",
-        [id],
-        |row| row.get(0)
-    ) {
-        Ok(owner) => owner,
-        Err(e) => return HttpResponse::NotFound().json(serde_json::json!({
-            "success": false,
-            "error": format!("Post not found: {}", e)
-        }))
-    };
+    let post_owner = db::run(&pool, move |conn| PostRepo::new(conn).employer_of(id))
+        .await?
+        .ok_or(ApiError::NotFound("Post not found"))?;
 
-    if post_owner != auth_header {
-        return HttpResponse::Forbidden().json(serde_json::json!({
-            "success": false,
-            "error": "You do not have permission to update this post"
-        }));
+    if post_owner != user.unique_id {
+        return Err(ApiError::Forbidden("You do not have permission to update this post"));
     }
 
-    let post = body.into_inner();
-    match conn.execute(
-        "UPDATE posts SET 
-            title = ?,
-            description = ?,
-            tags = ?,
-            documents = ?,
-            tips = ?,
-            skills = ?,
-            experience = ?,
-            jobtype = ?,
-            location = ?,
-            date = ?,
-            questions = ?
-        WHERE id = ?",
-        rusqlite::params![
-            post.title,
-            post.description,
-            serde_json::to_string(&post.tags).unwrap(),
-            post.documents,
-            serde_json::to_string(&post.tips).unwrap(),
-            serde_json::to_string(&post.skills).unwrap(),
-            post.experience,
-            post.jobtype,
-            post.location,
-            post.date,
-            serde_json::to_string(&post.questions).unwrap(),
-            id
-        ],
-    ) {
-        Ok(_) => HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "message": "Post updated successfully"
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
-            "success": false,
-            "error": format!("Failed to update post: {}", e)
-        }))
-    }
-}
\ No newline at end of file
+    let changes = body.into_inner();
+    db::run(&pool, move |conn| PostRepo::new(conn).update(id, &changes)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Post updated successfully"
+    })))
+}
@@ -0,0 +1,145 @@
+use actix_web::{get, patch, web, HttpResponse};
+use rusqlite::TransactionBehavior;
+use serde_json::Value;
+use validator::Validate;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::AccountRepo;
+use crate::users::ProfileInfo;
+use crate::validation::FieldError;
+
+// RFC 7396 merge patch: objects merge key by key, null removes a key, anything else
+// (arrays included) replaces what was there.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(entries) => {
+            if !target.is_object() {
+                *target = Value::Object(serde_json::Map::new());
+            }
+            if let Value::Object(target) = target {
+                for (key, value) in entries {
+                    match value {
+                        Value::Null => {
+                            target.remove(key);
+                        },
+                        _ => merge_patch(target.entry(key.clone()).or_insert(Value::Null), value),
+                    }
+                }
+            }
+        },
+        _ => *target = patch.clone(),
+    }
+}
+
+fn field_path(prefix: &str, key: &str) -> String {
+    match prefix {
+        "" => key.to_string(),
+        _ => format!("{}.{}", prefix, key),
+    }
+}
+
+// Keys in the patch that the saved profile doesn't have. They would be dropped without a
+// trace, so they are reported instead.
+fn unknown_fields(patch: &Value, saved: &Value, prefix: &str, out: &mut Vec<FieldError>) {
+    if let (Value::Object(patch), Value::Object(saved)) = (patch, saved) {
+        for (key, value) in patch {
+            match saved.get(key) {
+                Some(saved) => unknown_fields(value, saved, &field_path(prefix, key), out),
+                None if !value.is_null() => out.push(FieldError {
+                    field: field_path(prefix, key),
+                    code: "unknown_field".to_string(),
+                    message: "is not a profile field".to_string(),
+                }),
+                None => {}
+            }
+        }
+    }
+}
+
+// The patched profile is decoded back into `ProfileInfo` and validated as a whole, so the
+// stored JSON always matches the schema.
+fn apply_patch(current: &ProfileInfo, patch: &Value) -> Result<ProfileInfo, ApiError> {
+    let mut merged = serde_json::to_value(current).map_err(|e| ApiError::Internal(e.to_string()))?;
+    merge_patch(&mut merged, patch);
+
+    let profile: ProfileInfo = serde_path_to_error::deserialize(merged).map_err(|e| {
+        let field = match e.path().to_string().as_str() {
+            "." => "profile".to_string(),
+            path => path.to_string(),
+        };
+        ApiError::Validation(vec![FieldError {
+            field,
+            code: "invalid".to_string(),
+            message: e.inner().to_string(),
+        }])
+    })?;
+    profile.validate()?;
+
+    let saved = serde_json::to_value(&profile).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let mut unknown = Vec::new();
+    unknown_fields(patch, &saved, "", &mut unknown);
+    if !unknown.is_empty() {
+        return Err(ApiError::Validation(unknown));
+    }
+    Ok(profile)
+}
+
+#[get("/api/v1/user/profile")]
+pub async fn get_profile(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let unique_id = user.unique_id.clone();
+    let profile = db::run(&pool, move |conn| AccountRepo::new(conn).find_by_unique_id(&unique_id))
+        .await?
+        .ok_or(ApiError::NotFound("User not found"))?
+        .profile;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "profile": profile
+    })))
+}
+
+// Takes a JSON merge patch (RFC 7396) over the profile, e.g. {"bio": "...", "contact": {"city": "..."}}.
+// Forms and tasks are read-only here.
+#[patch("/api/v1/user/profile")]
+pub async fn update_profile(user: AuthenticatedUser, req_body: web::Json<Value>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let patch = req_body.into_inner();
+    let sections = match &patch {
+        Value::Object(sections) => sections,
+        _ => return Err(ApiError::BadRequest("Profile patch must be a JSON object".to_string())),
+    };
+    let read_only: Vec<FieldError> = ProfileInfo::READ_ONLY_SECTIONS
+        .iter()
+        .filter(|section| sections.contains_key(**section))
+        .map(|section| FieldError {
+            field: section.to_string(),
+            code: "read_only".to_string(),
+            message: "cannot be changed through the profile".to_string(),
+        })
+        .collect();
+    if !read_only.is_empty() {
+        return Err(ApiError::Validation(read_only));
+    }
+
+    let unique_id = user.unique_id.clone();
+    let profile = db::run(&pool, move |conn| {
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = match AccountRepo::new(&tx).find_by_unique_id(&unique_id)? {
+            Some(account) => account.profile,
+            None => return Ok(Err(ApiError::NotFound("User not found")))
+        };
+        let profile = match apply_patch(&current, &patch) {
+            Ok(profile) => profile,
+            Err(e) => return Ok(Err(e))
+        };
+        AccountRepo::new(&tx).update_profile(&unique_id, &profile)?;
+        tx.commit()?;
+        Ok(Ok(profile))
+    }).await??;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "profile": profile
+    })))
+}
@@ -0,0 +1,46 @@
+use actix_web::{delete, get, web, HttpResponse};
+
+use crate::auth::AuthenticatedUser;
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::login_events;
+use crate::sessions;
+
+// Devices that are signed in to the account, i.e. refresh token families that can still be refreshed.
+#[get("/api/v1/user/sessions")]
+pub async fn list_sessions(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let unique_id = user.unique_id.clone();
+    let sessions = db::run(&pool, move |conn| sessions::list(conn, &unique_id)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "sessions": sessions
+    })))
+}
+
+// Signs the session out: it can no longer be refreshed, and its access tokens stop working immediately.
+#[delete("/api/v1/user/sessions/{id}")]
+pub async fn revoke_session(user: AuthenticatedUser, path: web::Path<String>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let session_id = path.into_inner();
+    let unique_id = user.unique_id.clone();
+    let revoked = db::run(&pool, move |conn| sessions::revoke_session(conn, &unique_id, &session_id)).await?;
+    if revoked == 0 {
+        return Err(ApiError::NotFound("Session not found"));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Session revoked"
+    })))
+}
+
+#[get("/api/v1/user/logins")]
+pub async fn login_history(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let unique_id = user.unique_id.clone();
+    let logins = db::run(&pool, move |conn| login_events::recent(conn, &unique_id, 50)).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "logins": logins
+    })))
+}
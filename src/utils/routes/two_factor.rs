@@ -0,0 +1,120 @@
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::account_routes::check_current_password;
+use crate::auth::{client_info, AuthenticatedUser, EnrollingUser};
+use crate::db::{self, DbPool};
+use crate::errors::ApiError;
+use crate::repository::AccountRepo;
+use crate::throttle;
+use crate::two_factor::{self, TwoFactorError};
+use crate::validation::{FieldError, ValidatedJson};
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct TwoFactorCodeRequest {
+    #[validate(length(min = 1, max = 32))]
+    code: String
+}
+
+#[derive(Default, Deserialize, Validate)]
+#[serde(default)]
+pub struct DisableTwoFactorRequest {
+    // Left empty by accounts without a password (see `check_current_password`).
+    #[validate(length(max = 128))]
+    current_password: String,
+    #[validate(length(min = 1, max = 32))]
+    code: String
+}
+
+// A wrong code while managing 2FA is a form error, not a failed login.
+fn code_error(e: TwoFactorError) -> ApiError {
+    match e {
+        TwoFactorError::InvalidCode => ApiError::Validation(vec![FieldError {
+            field: "code".to_string(),
+            code: "incorrect".to_string(),
+            message: "is incorrect".to_string(),
+        }]),
+        other => other.into(),
+    }
+}
+
+// Generates a new secret for the authenticator app. 2FA stays off until `/confirm`.
+#[post("/api/v1/user/2fa/setup")]
+pub async fn setup(user: EnrollingUser, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    if user.two_factor_enabled {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled"));
+    }
+
+    let unique_id = user.unique_id.clone();
+    let (email, secret) = db::run(&pool, move |conn| {
+        let email = match AccountRepo::new(conn).find_by_unique_id(&unique_id)? {
+            Some(account) => account.email,
+            None => return Ok(None)
+        };
+        Ok(Some((email, two_factor::begin_setup(conn, &unique_id)?)))
+    }).await?.ok_or(ApiError::NotFound("User not found"))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "secret": secret,
+        "otpauth_uri": two_factor::provisioning_uri(&secret, &email)
+    })))
+}
+
+#[post("/api/v1/user/2fa/confirm")]
+pub async fn confirm(user: EnrollingUser, req_body: ValidatedJson<TwoFactorCodeRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    if user.two_factor_enabled {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled"));
+    }
+
+    let unique_id = user.unique_id.clone();
+    let code = req_body.into_inner().code;
+    let recovery_codes = db::run(&pool, move |conn| Ok(two_factor::confirm(conn, &unique_id, code.trim())))
+        .await?
+        .map_err(code_error)?;
+    println!("[LOG] Two-factor authentication enabled for account {}", user.unique_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "recovery_codes": recovery_codes,
+        "message": "Two-factor authentication enabled. Store these recovery codes somewhere safe, they won't be shown again"
+    })))
+}
+
+// Needs the current password as well as a code, so a stolen access token alone can't turn
+// 2FA off. Codes are guessed against the same allowance as at sign-in.
+#[post("/api/v1/user/2fa/disable")]
+pub async fn disable(req: HttpRequest, user: AuthenticatedUser, req_body: ValidatedJson<DisableTwoFactorRequest>, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    if !user.two_factor_enabled {
+        return Err(ApiError::Conflict("Two-factor authentication is not enabled"));
+    }
+
+    let DisableTwoFactorRequest { current_password, code } = req_body.into_inner();
+    let account = check_current_password(&pool, &user, current_password).await?;
+    let unique_id = user.unique_id.clone();
+    let account_type = user.account_type;
+    let ip = client_info(&req).ip;
+    db::run(&pool, move |conn| {
+        if two_factor::is_required(conn, account_type)? {
+            return Ok(Err(ApiError::Forbidden("Two-factor authentication is required for your account type")));
+        }
+        if let Some(seconds) = throttle::reserve_attempt(conn, &account.email, &ip)? {
+            return Ok(Err(ApiError::TooManyAttempts(seconds)));
+        }
+        if let Err(e) = two_factor::check_code(conn, &unique_id, &code) {
+            throttle::record_failure(conn, &account.email, &ip)?;
+            return Ok(Err(code_error(e)));
+        }
+        throttle::record_success(conn, &account.email, &ip)?;
+        two_factor::disable(conn, &unique_id)?;
+        Ok(Ok(()))
+    }).await??;
+    println!("[LOG] Two-factor authentication disabled for account {}", user.unique_id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Two-factor authentication disabled"
+    })))
+}
@@ -0,0 +1,106 @@
+use std::env;
+use std::fmt;
+
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use jwt::{SignWithKey, VerifyWithKey};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+// Access tokens are short-lived; clients call `/api/v1/auth/refresh` with their refresh token for a new one.
+pub const ACCESS_TOKEN_TTL_SECONDS: i64 = 15 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,          // accounts.unique_id
+    pub sid: String,          // refresh_tokens.family_id, checked on every request
+    pub account_type: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug)]
+pub enum TokenError {
+    MissingSecret,
+    MissingToken,
+    Invalid,
+    Expired,
+    Revoked,
+    Signing,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            TokenError::MissingSecret => "JWT_SECRET is not set or shorter than 32 characters",
+            TokenError::MissingToken => "Missing authorization header",
+            TokenError::Invalid => "Invalid authorization token",
+            TokenError::Expired => "Authorization token has expired",
+            TokenError::Revoked => "Session has been signed out",
+            TokenError::Signing => "Failed to sign authorization token",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+// Each deployment sets its own secret; anyone who knows it can mint tokens for any account.
+const MIN_SECRET_LENGTH: usize = 32;
+
+fn signing_key() -> Result<Hmac<Sha256>, TokenError> {
+    let secret = env::var("JWT_SECRET").map_err(|_| TokenError::MissingSecret)?;
+    if secret.len() < MIN_SECRET_LENGTH {
+        return Err(TokenError::MissingSecret);
+    }
+    Hmac::new_from_slice(secret.as_bytes()).map_err(|_| TokenError::MissingSecret)
+}
+
+// Called at startup so a missing secret stops the server instead of failing every login.
+pub fn check_secret() -> Result<(), TokenError> {
+    signing_key().map(|_| ())
+}
+
+pub fn issue_access_token(unique_id: &str, session_id: &str, account_type: &str) -> Result<String, TokenError> {
+    let key = signing_key()?;
+    let now = chrono::Utc::now().timestamp();
+    let claims = Claims {
+        sub: unique_id.to_string(),
+        sid: session_id.to_string(),
+        account_type: account_type.to_string(),
+        iat: now,
+        exp: now + ACCESS_TOKEN_TTL_SECONDS,
+    };
+    claims.sign_with_key(&key).map_err(|_| TokenError::Signing)
+}
+
+pub fn verify_access_token(token: &str) -> Result<Claims, TokenError> {
+    let key = signing_key()?;
+    let claims: Claims = token.verify_with_key(&key).map_err(|_| TokenError::Invalid)?;
+    if claims.exp <= chrono::Utc::now().timestamp() {
+        return Err(TokenError::Expired);
+    }
+    Ok(claims)
+}
+
+// Reads `Authorization: Bearer <jwt>` and returns the verified claims.
+pub fn authenticate(req: &HttpRequest) -> Result<Claims, TokenError> {
+    let header = req.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .ok_or(TokenError::MissingToken)?;
+    let token = header.strip_prefix("Bearer ").ok_or(TokenError::MissingToken)?;
+    verify_access_token(token.trim())
+}
+
+// Opaque, high-entropy secret handed to clients (refresh tokens and similar).
+pub fn generate_opaque_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+// Only this digest is stored server-side, so a leaked database can't be replayed.
+pub fn hash_opaque_token(token: &str) -> String {
+    use sha2::Digest;
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}